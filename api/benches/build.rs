@@ -0,0 +1,93 @@
+use api::blocks::{Blocks, BuildConfig};
+use api::chaos::{ChaosConfig, ChaosServerApi, Latency};
+use api::generator::{ChainGenerator, GeneratorConfig};
+use api::server::*;
+use api::store::BlockStore;
+use core::ops::Range;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+const LEN: u32 = 2_000;
+
+/// The former parallel builder: one task and two requests per height, all at once
+async fn build_per_height<S: ServerAPI + Send + Sync + 'static>(
+    server: Arc<S>,
+    block_height_range: Range<u32>,
+) -> Vec<Block> {
+    let tasks: Vec<_> = block_height_range
+        .map(|height| {
+            let server = server.clone();
+            tokio::spawn(async move {
+                let header = server.block_headers(height..height + 1).await.unwrap()[0];
+                server
+                    .build_block_transactions(header, height)
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect();
+    let mut blocks = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        blocks.push(task.await.unwrap());
+    }
+    blocks
+}
+
+fn bench_builders(c: &mut Criterion, name: &str, latency: Latency) {
+    let runtime = Runtime::new().unwrap();
+    let store: BlockStore = ChainGenerator::new(GeneratorConfig::default())
+        .take(LEN as usize)
+        .collect();
+    let server = Arc::new(ChaosServerApi::new(
+        store,
+        ChaosConfig {
+            latency,
+            ..Default::default()
+        },
+    ));
+
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    group.bench_function("per height", |b| {
+        b.to_async(&runtime)
+            .iter(|| build_per_height(server.clone(), 0..LEN))
+    });
+    for concurrency in [4, 16, 64] {
+        let config = BuildConfig {
+            concurrency,
+            ..Default::default()
+        };
+        group.bench_with_input(
+            BenchmarkId::new("bounded", concurrency),
+            &config,
+            |b, &config| {
+                b.to_async(&runtime)
+                    .iter(|| server.clone().build_blocks_parallel_with(0..LEN, config))
+            },
+        );
+    }
+    // Blocks depending on their parent
+    let config = BuildConfig::default();
+    group.bench_function("sequential", |b| {
+        b.to_async(&runtime)
+            .iter(|| server.build_blocks_sequential(0..LEN, config))
+    });
+    group.bench_function("pipelined", |b| {
+        b.to_async(&runtime)
+            .iter(|| server.clone().build_blocks_pipelined(0..LEN, config))
+    });
+    group.finish();
+}
+
+fn local(c: &mut Criterion) {
+    bench_builders(c, "build local", Latency::None);
+}
+
+fn remote(c: &mut Criterion) {
+    bench_builders(c, "build remote", Latency::Fixed(Duration::from_millis(2)));
+}
+
+criterion_group!(benches, local, remote);
+criterion_main!(benches);
@@ -1,230 +1,1184 @@
+use crate::merkle::merkle_root;
 use crate::server::*;
+use crate::state::WorldState;
 use async_trait::async_trait;
 use core::ops::Range;
 use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{BoxStream, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::task::{JoinError, JoinHandle};
+
+/// The error returned by the block builders.
+#[derive(Debug, Clone, Serialize)]
+pub enum BuildError {
+    /// The server could not return the requested data.
+    Server(ServerError),
+    /// A block was rejected during verification or execution.
+    StateTransition(StateTransitionError),
+    /// A built block could not be stored in the [`BlockSink`].
+    Storage(String),
+    /// The task building some of the blocks panicked or was cancelled.
+    Task(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Server(e) => e.fmt(f),
+            BuildError::StateTransition(e) => e.fmt(f),
+            BuildError::Storage(message) => write!(f, "Storage error: {}", message),
+            BuildError::Task(message) => write!(f, "Task error: {}", message),
+        }
+    }
+}
+
+impl From<ServerError> for BuildError {
+    fn from(e: ServerError) -> Self {
+        BuildError::Server(e)
+    }
+}
+
+impl From<StateTransitionError> for BuildError {
+    fn from(e: StateTransitionError) -> Self {
+        BuildError::StateTransition(e)
+    }
+}
+
+///
+/// Trusted header hashes at known heights
+///
+/// The blocks below a checkpoint do not have to be verified and executed
+/// again, as long as their headers are linked by their parent hashes up to
+/// the checkpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checkpoints {
+    hashes: BTreeMap<u32, BlockHash>,
+}
+
+impl Checkpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the header with the hash `hash` at `height`
+    pub fn insert(&mut self, height: u32, hash: BlockHash) -> Option<BlockHash> {
+        self.hashes.insert(height, hash)
+    }
+
+    pub fn get(&self, height: u32) -> Option<&BlockHash> {
+        self.hashes.get(&height)
+    }
+
+    /// The height below which the headers starting at `start` are trusted
+    ///
+    /// Every header at a checkpoint height has to match its checkpoint, and
+    /// the headers below the highest one have to be at their height and
+    /// linked by their parent hashes. Only the checkpoints within the
+    /// headers count, so `start` is returned when there are none.
+    pub fn trusted_end(
+        &self,
+        start: u32,
+        headers: &[BlockHeader],
+    ) -> Result<u32, StateTransitionError> {
+        if headers.is_empty() {
+            return Ok(start);
+        }
+        let end = start + headers.len() as u32;
+        let mut trusted = start;
+        for (&height, hash) in self.hashes.range(start..end) {
+            if headers[(height - start) as usize].hash() != *hash {
+                return Err(StateTransitionError::checkpoint_mismatch(height));
+            }
+            trusted = height;
+        }
+        let linked = &headers[..(trusted - start) as usize + 1];
+        for (height, header) in (start..).zip(linked) {
+            if header.block_height != height {
+                return Err(StateTransitionError::invalid_header(height));
+            }
+        }
+        for (height, pair) in (start + 1..).zip(linked.windows(2)) {
+            if pair[1].parent_hash != pair[0].hash() {
+                return Err(StateTransitionError::parent_mismatch(height));
+            }
+        }
+        Ok(trusted)
+    }
+}
+
+impl FromIterator<(u32, BlockHash)> for Checkpoints {
+    fn from_iter<I: IntoIterator<Item = (u32, BlockHash)>>(iter: I) -> Self {
+        Checkpoints {
+            hashes: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// The order in which [`Blocks::build_blocks_stream`] yields the blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamOrder {
+    /// In height order, the batches built ahead waiting for the lower ones.
+    #[default]
+    Ordered,
+    /// As soon as their batch is built.
+    Unordered,
+}
+
+/// What a builder does with the heights after a failing one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailureMode {
+    /// Stop at the first failing height, the heights after it are not built.
+    #[default]
+    FailFast,
+    /// Build every height of the range whatever the others gave.
+    ///
+    /// Only [`Blocks::build_blocks_report`] collects all the failures; the
+    /// builders returning a `Result` stop at the first one.
+    CollectAll,
+}
+
+///
+/// The outcome of every height of a build
+///
+/// A height is either built, with its block in `blocks`, or failed, with
+/// its error in `failures`. With [`FailureMode::FailFast`], the heights
+/// after the first failure are in neither.
+#[derive(Debug, Clone, Default)]
+pub struct BuildReport {
+    /// The built blocks, in height order.
+    pub blocks: Vec<Block>,
+    /// Why each failed height was not built.
+    pub failures: BTreeMap<u32, BuildError>,
+}
+
+impl BuildReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// The blocks, or the failure at the lowest height
+    pub fn into_result(self) -> Result<Vec<Block>, BuildError> {
+        match self.failures.into_values().next() {
+            Some(e) => Err(e),
+            None => Ok(self.blocks),
+        }
+    }
+
+    fn add(&mut self, height: u32, result: Result<Block, BuildError>) {
+        match result {
+            Ok(block) => self.blocks.push(block),
+            Err(e) => {
+                self.failures.insert(height, e);
+            }
+        }
+    }
+}
+
+/// How the builders split the range and retry failed requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildConfig {
+    /// Most batches built at the same time, so most requests in flight.
+    pub concurrency: usize,
+    /// Heights fetched by each request.
+    pub batch_len: u32,
+    /// Times a request failing with [`ServerError::Unavailable`],
+    /// [`ServerError::Transport`] or [`ServerError::Throttled`] is sent again.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each following one.
+    pub retry_delay: Duration,
+    pub failure_mode: FailureMode,
+}
+
+impl BuildConfig {
+    /// The same config stopping at the first failure, for the builders returning a `Result`
+    fn fail_fast(self) -> Self {
+        BuildConfig {
+            failure_mode: FailureMode::FailFast,
+            ..self
+        }
+    }
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        BuildConfig {
+            concurrency: 16,
+            batch_len: STREAM_CHUNK_LEN,
+            retries: 2,
+            retry_delay: Duration::from_millis(50),
+            failure_mode: FailureMode::FailFast,
+        }
+    }
+}
+
+///
+/// Destination of the built blocks
+///
+/// Kept apart from the server the blocks are fetched from, so the blocks
+/// served by an HTTP client can for instance be stored on disk.
+pub trait BlockSink {
+    fn store(&mut self, block: Block) -> Result<(), BuildError>;
+}
+
+impl BlockSink for Vec<Block> {
+    fn store(&mut self, block: Block) -> Result<(), BuildError> {
+        self.push(block);
+        Ok(())
+    }
+}
+
+impl BlockSink for BlockList {
+    fn store(&mut self, block: Block) -> Result<(), BuildError> {
+        self.insert_at_tail(block);
+        Ok(())
+    }
+}
 
 ///
 ///
-/// Trait to implement double linked list on Block type
+/// Trait building the blocks fetched from any `ServerAPI`
 #[async_trait]
 pub trait Blocks {
-    fn get_block_header_at(&mut self, height: u32) -> Option<Block>;
-
     async fn build_block_transactions(
         &self,
         block_header: BlockHeader,
         height: u32,
-    ) -> Result<Block, StateTransitionError>;
+    ) -> Result<Block, BuildError>;
 
     async fn build_blocks_parallel(
         self: Arc<Self>,
         block_height_range: Range<u32>,
-    ) -> Result<Vec<Block>, ServerError>;
+    ) -> Result<Vec<Block>, BuildError>;
+
+    async fn build_blocks_parallel_with(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError>;
+
+    async fn build_blocks_report(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> BuildReport;
+
+    fn build_blocks_stream(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+        order: StreamOrder,
+    ) -> BoxStream<'static, Result<Block, BuildError>>;
+
+    async fn build_blocks_checkpointed(
+        &self,
+        checkpoints: &Checkpoints,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError>;
+
+    async fn build_blocks_sequential(
+        &self,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError>;
+
+    async fn build_blocks_pipelined(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError>;
+
+    async fn build_blocks_sequential_on(
+        &self,
+        state: &mut WorldState,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError>;
+
+    async fn build_blocks_pipelined_on(
+        self: Arc<Self>,
+        state: &mut WorldState,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError>;
 
-    // #[async_recursion(?Send)]
     fn build_blocks_backward(
         &self,
         blocks: Vec<Block>,
         block_height_range: Range<u32>,
-    ) -> BoxFuture<'_, Result<Vec<Block>, ServerError>>;
+    ) -> BoxFuture<'_, Result<Vec<Block>, BuildError>>;
 
-    // #[async_recursion(?Send)]
     fn build_blocks_forward(
         &self,
         blocks: Vec<Block>,
         block_height_range: Range<u32>,
-    ) -> BoxFuture<'_, Result<Vec<Block>, ServerError>>;
+    ) -> BoxFuture<'_, Result<Vec<Block>, BuildError>>;
+
+    /// Build the blocks in parallel and store them in `sink`, in height order
+    async fn build_blocks_into<D: BlockSink + Send>(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+        sink: &mut D,
+    ) -> Result<(), BuildError> {
+        for block in self.build_blocks_parallel(block_height_range).await? {
+            sink.store(block)?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
-impl Blocks for BlockList {
-    /// Get the block at some height
+impl<S: ServerAPI + Send + Sync + ?Sized + 'static> Blocks for S {
+    /// Build the build independently X does not depend on X - 1
+    ///
+    /// Same as [`Blocks::build_blocks_parallel_with`] with the default [`BuildConfig`]
+    async fn build_blocks_parallel(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<Block>, BuildError> {
+        self.build_blocks_parallel_with(block_height_range, BuildConfig::default())
+            .await
+    }
+
+    /// Build the blocks independently, a batch of heights at a time
+    ///
+    /// Same as [`Blocks::build_blocks_report`], with the failure at the
+    /// lowest height as the error.
+    async fn build_blocks_parallel_with(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError> {
+        self.build_blocks_report(block_height_range, config)
+            .await
+            .into_result()
+    }
+
+    /// Build the blocks independently and report the outcome of each height
     ///
-    /// (Not used)
-    fn get_block_header_at(&mut self, height: u32) -> Option<Block> {
-        for block in self.iter() {
-            if block.header.block_height == height {
-                return Some(block);
+    /// Each batch is built by its own task, with one request for its headers
+    /// and one for their transactions. At most `config.concurrency` tasks are
+    /// spawned at once, and the batches are reported in height order. With
+    /// [`FailureMode::FailFast`] no batch is spawned after the first failure,
+    /// and the ones still building are aborted.
+    async fn build_blocks_report(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> BuildReport {
+        let batches = batches(block_height_range.clone(), config.batch_len);
+        let mut built = futures::stream::iter(batches)
+            .map(|batch| spawn_batch(&self, batch, config))
+            .buffered(config.concurrency.max(1));
+        let mut report = BuildReport {
+            blocks: Vec::with_capacity(block_height_range.len()),
+            failures: BTreeMap::new(),
+        };
+        while let Some((batch, results)) = built.next().await {
+            for (height, result) in batch.zip(results) {
+                report.add(height, result);
+                if config.failure_mode == FailureMode::FailFast && !report.is_success() {
+                    return report;
+                }
             }
         }
-        return None;
+        report
     }
 
-    /// Build the build independently X does not depend on X - 1
+    /// Build the blocks independently and yield each one once its batch is built
     ///
-    /// Spawn an async thread for each block height
-    async fn build_blocks_parallel(
+    /// The batches are built like in [`Blocks::build_blocks_report`], by at
+    /// most `config.concurrency` tasks. [`StreamOrder::Ordered`] yields the
+    /// blocks in height order: only the `config.concurrency` batches after
+    /// the lowest one not yielded are built ahead. With
+    /// [`FailureMode::FailFast`] the stream ends after the first error.
+    /// Dropping the stream aborts the batches still building.
+    fn build_blocks_stream(
         self: Arc<Self>,
         block_height_range: Range<u32>,
-    ) -> Result<Vec<Block>, ServerError> {
-        let mut blocks = vec![];
-        let res_blocks: Vec<_> = block_height_range
-            .into_iter()
-            .map(|i| {
-                let s = self.clone();
-                return tokio::spawn(async move {
-                    let block_header = s.block_headers(i..i + 1).await;
-                    let header = block_header
-                        .map_err(|e| e.to_string())
-                        .expect("header returned from the server, check if your range is correct"); // needs better error handling
-                    let res_block: Vec<_> = header
-                        .clone()
-                        .into_iter()
-                        .map(|bh| {
-                            return s.build_block_transactions(bh, i);
-                        })
-                        .collect();
-                    let mut new_block = futures::future::join_all(res_block).await;
-                    return new_block.pop().unwrap();
-                });
-            })
-            .collect();
-        let results = futures::future::join_all(res_blocks).await;
-        for res in results {
-            if let Ok(block) = res {
-                blocks.push(block.clone().unwrap())
-            }
+        config: BuildConfig,
+        order: StreamOrder,
+    ) -> BoxStream<'static, Result<Block, BuildError>> {
+        let batches = batches(block_height_range, config.batch_len);
+        let built =
+            futures::stream::iter(batches).map(move |batch| spawn_batch(&self, batch, config));
+        let concurrency = config.concurrency.max(1);
+        let built = match order {
+            StreamOrder::Ordered => built.buffered(concurrency).boxed(),
+            StreamOrder::Unordered => built.buffer_unordered(concurrency).boxed(),
+        };
+        let blocks = built.flat_map(|(_, results)| futures::stream::iter(results));
+        match config.failure_mode {
+            FailureMode::FailFast => blocks
+                .scan(false, |failed, block| {
+                    let next = match *failed {
+                        true => None,
+                        false => Some(block),
+                    };
+                    *failed = next.as_ref().is_none_or(Result::is_err);
+                    futures::future::ready(next)
+                })
+                .boxed(),
+            FailureMode::CollectAll => blocks.boxed(),
         }
-        Ok(blocks)
     }
 
     /// Request the transactions for a given block height
     ///
     /// Build the block from the returned transactions from the server and the given block header
+    ///
+    /// The transactions have to match the transactions root of the header before being executed
     async fn build_block_transactions(
         &self,
         block_header: BlockHeader,
         height: u32,
-    ) -> Result<Block, StateTransitionError> {
-        if !block_header.verify() {
-            return Err(StateTransitionError);
-        }
-        let block_transactions = self.block_transactions(height..height + 1).await;
-        let transactions = block_transactions
-            .map_err(|e| e.to_string())
-            .expect("transactions returned from the server; check if your range is correct");
-        let mut txns: Vec<_> = transactions
-            .into_iter()
-            .map(|txs| match validate_block_transactions(txs.clone()) {
-                Ok(()) => {
-                    return Ok(Block {
-                        header: block_header,
-                        transactions: txs,
-                    });
-                }
-                Err(_) => return Err(StateTransitionError),
-            })
-            .collect();
-        if let Some(block) = txns.pop() {
-            return block;
+    ) -> Result<Block, BuildError> {
+        if block_header.block_height != height || !block_header.verify() {
+            return Err(StateTransitionError::invalid_header(height).into());
+        }
+        let mut transactions = self.block_transactions(height..height + 1).await?;
+        match transactions.pop() {
+            Some(txs) => Ok(checked_block(height, block_header, txs)?),
+            None => Err(ServerError::MissingHeight(height).into()),
+        }
+    }
+
+    /// Build the blocks independently, trusting the ones below a checkpoint
+    ///
+    /// The headers of the range are fetched first and checked against the
+    /// checkpoints. The blocks below the highest checkpoint of the range are
+    /// neither verified nor executed, their transactions only have to match
+    /// the transactions root; the other blocks are built as usual.
+    async fn build_blocks_checkpointed(
+        &self,
+        checkpoints: &Checkpoints,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError> {
+        let config = config.fail_fast();
+        let start = block_height_range.start;
+        let mut headers = Fetched::default();
+        for batch in batches(block_height_range.clone(), config.batch_len) {
+            headers.extend(fetch_prefix(self, fetch_headers, batch, &config).await);
+            if let Some(e) = headers.error {
+                return Err(e.into());
+            }
+        }
+        let headers = headers.items;
+        let trusted = checkpoints.trusted_end(start, &headers)?;
+        let blocks = batches(block_height_range, config.batch_len).map(|batch| {
+            let headers = &headers[(batch.start - start) as usize..(batch.end - start) as usize];
+            let trusted = trusted.clamp(batch.start, batch.end) - batch.start;
+            let (trusted, verified) = headers.split_at(trusted as usize);
+            let verified_start = batch.start + trusted.len() as u32;
+            let trusted = trusted.iter().copied().map(Ok).collect();
+            let verified = verified_headers(verified_start, verified.iter().copied().map(Ok));
+            async move {
+                let mut blocks = build_bodies(self, batch.start, trusted, false, &config).await;
+                blocks.extend(build_bodies(self, verified_start, verified, true, &config).await);
+                blocks.into_iter().collect::<Result<Vec<_>, _>>()
+            }
+        });
+        let mut built = futures::stream::iter(blocks).buffered(config.concurrency.max(1));
+        let mut blocks = Vec::with_capacity(headers.len());
+        while let Some(batch) = built.next().await {
+            blocks.extend(batch?);
         }
-        return Err(StateTransitionError);
+        Ok(blocks)
+    }
+
+    /// Build the block where X depends on X - 1, one batch after the other
+    ///
+    /// Each header has to link to the block built before it by its parent
+    /// hash, and is only verified once that block is built. The block at the
+    /// start of the range is the first one: the heights below are not
+    /// fetched, so its parent is not checked. The loop keeps the stack
+    /// constant whatever the length of the range.
+    async fn build_blocks_sequential(
+        &self,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError> {
+        build_sequential(self, None, block_height_range, config).await
+    }
+
+    /// Build the block where X depends on X - 1, fetching ahead in parallel
+    ///
+    /// Up to `config.concurrency` batches are prefetched by their own task:
+    /// the headers, then the transactions of the headers passing
+    /// [`BlockHeader::verify`]. The blocks are linked to their parent and
+    /// executed strictly in height order as the batches arrive, like
+    /// [`Blocks::build_blocks_sequential`]. The first failure aborts the
+    /// prefetching of the following batches.
+    async fn build_blocks_pipelined(
+        self: Arc<Self>,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError> {
+        build_pipelined(self, None, block_height_range, config).await
+    }
+
+    /// Build the block where X depends on X - 1 and execute it on `state`
+    ///
+    /// Same as [`Blocks::build_blocks_sequential`], each block is then
+    /// applied to `state`, the world state before the start of the range, so
+    /// a transfer without funds or out of nonce order fails with
+    /// `InsufficientBalance` or `BadNonce`. On failure, `state` is the state
+    /// after the blocks below the failing one.
+    async fn build_blocks_sequential_on(
+        &self,
+        state: &mut WorldState,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError> {
+        build_sequential(self, Some(state), block_height_range, config).await
+    }
+
+    /// Build the block where X depends on X - 1 ahead in parallel and execute it on `state`
+    ///
+    /// Same as [`Blocks::build_blocks_pipelined`], with `state` like in
+    /// [`Blocks::build_blocks_sequential_on`].
+    async fn build_blocks_pipelined_on(
+        self: Arc<Self>,
+        state: &mut WorldState,
+        block_height_range: Range<u32>,
+        config: BuildConfig,
+    ) -> Result<Vec<Block>, BuildError> {
+        build_pipelined(self, Some(state), block_height_range, config).await
     }
 
     /// Build the block where X depends on X - 1
     ///
-    /// Recursive backward
+    /// Same as [`Blocks::build_blocks_sequential`] with the default
+    /// [`BuildConfig`], the blocks are appended to `blocks`
     fn build_blocks_backward(
         &self,
-        blocks: Vec<Block>,
+        mut blocks: Vec<Block>,
         block_height_range: Range<u32>,
-    ) -> BoxFuture<'_, Result<Vec<Block>, ServerError>> {
+    ) -> BoxFuture<'_, Result<Vec<Block>, BuildError>> {
         async move {
-            if block_height_range.start == block_height_range.end {
-                return Ok(blocks);
-            }
-            let mut iter = self.iter();
-            if iter.next_back().is_none() {
-                return Ok(blocks);
-            }
-            // println!("build_blocks_backward {:#?}", block_height_range);
-            let previous_block = self
-                .build_blocks_backward(blocks, block_height_range.start..block_height_range.end - 1)
-                .await;
-            if previous_block.as_ref().ok().is_some() {
-                iter.next();
-                let block_header = self
-                    .block_headers(block_height_range.end - 1..block_height_range.end)
-                    .await;
-                let header = block_header.map_err(|e| e.to_string()).unwrap();
-                let res2: Vec<_> = header
-                    .clone()
-                    .into_iter()
-                    .map(|bh| {
-                        return self.build_block_transactions(bh, block_height_range.end - 1);
-                    })
-                    .collect();
-                let mut res = futures::future::join_all(res2).await;
-                match res.remove(0) {
-                    Ok(b) => {
-                        let mut previous_blocks = previous_block.unwrap();
-                        previous_blocks.push(b);
-                        return Ok(previous_blocks);
-                    }
-                    Err(_) => return Err(ServerError),
-                }
-            }
-            return Err(ServerError);
+            let built = self.build_blocks_sequential(block_height_range, BuildConfig::default());
+            blocks.extend(built.await?);
+            Ok(blocks)
         }
         .boxed()
     }
 
-    // #[async_recursion(?Send)]
     /// Build the block where X depends on X - 1
     ///
-    /// Recursive forward
-    ///
-    /// Recursive tail terminal but not to consider
+    /// Same as [`Blocks::build_blocks_backward`], but the blocks are appended
+    /// from the highest height to the lowest
     fn build_blocks_forward(
         &self,
         mut blocks: Vec<Block>,
         block_height_range: Range<u32>,
-    ) -> BoxFuture<'_, Result<Vec<Block>, ServerError>> {
+    ) -> BoxFuture<'_, Result<Vec<Block>, BuildError>> {
         async move {
-            if block_height_range.start == block_height_range.end {
-                return Ok(blocks);
+            let built = self.build_blocks_sequential(block_height_range, BuildConfig::default());
+            blocks.extend(built.await?.into_iter().rev());
+            Ok(blocks)
+        }
+        .boxed()
+    }
+}
+
+///
+/// Verified chain of headers, without the transactions
+///
+/// Each header passed [`BlockHeader::verify`] and links to the previous one
+/// by its parent hash. The first header is trusted as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderChain {
+    start: u32,
+    headers: Vec<BlockHeader>,
+}
+
+impl HeaderChain {
+    /// An empty chain whose first header is at `start`
+    pub fn new(start: u32) -> Self {
+        HeaderChain {
+            start,
+            headers: vec![],
+        }
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// The height of the next header to append
+    pub fn end(&self) -> u32 {
+        self.start + self.headers.len() as u32
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn get(&self, height: u32) -> Option<&BlockHeader> {
+        let index = height.checked_sub(self.start)?;
+        self.headers.get(index as usize)
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.headers.last()
+    }
+
+    /// Verify the header and append it at the end of the chain
+    pub fn push(&mut self, header: BlockHeader) -> Result<(), StateTransitionError> {
+        let height = self.end();
+        if header.block_height != height || !header.verify() {
+            return Err(StateTransitionError::invalid_header(height));
+        }
+        if self
+            .tip()
+            .is_some_and(|tip| tip.hash() != header.parent_hash)
+        {
+            return Err(StateTransitionError::parent_mismatch(height));
+        }
+        self.headers.push(header);
+        Ok(())
+    }
+}
+
+///
+/// Header-first client keeping only a verified [`HeaderChain`]
+///
+/// The bodies are not downloaded with the headers; [`LightClient::blocks`]
+/// fetches them on demand and checks them against the synced headers.
+#[derive(Debug)]
+pub struct LightClient<S> {
+    server: S,
+    headers: HeaderChain,
+}
+
+impl<S: ServerAPI + Sync> LightClient<S> {
+    /// A client syncing the headers from the height `start`
+    pub fn new(server: S, start: u32) -> Self {
+        LightClient {
+            server,
+            headers: HeaderChain::new(start),
+        }
+    }
+
+    pub fn headers(&self) -> &HeaderChain {
+        &self.headers
+    }
+
+    /// Download and verify the headers up to `end` excluded
+    ///
+    /// The headers are streamed from the server and appended as they arrive,
+    /// the ones verified before an error are kept.
+    pub async fn sync_headers(&mut self, end: u32) -> Result<(), BuildError> {
+        let mut headers = self.server.stream_block_headers(self.headers.end()..end);
+        while let Some(header) = headers.next().await {
+            self.headers.push(header?)?;
+        }
+        Ok(())
+    }
+
+    /// Fetch the blocks of the range, whose headers have to be synced
+    ///
+    /// The transactions of each block have to match the transactions root
+    /// of its header and be signed by their senders.
+    pub async fn blocks(&self, block_height_range: Range<u32>) -> Result<Vec<Block>, BuildError> {
+        let headers = block_height_range
+            .clone()
+            .map(|height| {
+                self.headers
+                    .get(height)
+                    .copied()
+                    .ok_or(ServerError::MissingHeight(height))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let config = BuildConfig::default();
+        let mut bodies = Fetched::default();
+        for batch in batches(block_height_range, config.batch_len) {
+            let fetched = fetch_prefix(&self.server, fetch_transactions, batch, &config);
+            bodies.extend(fetched.await);
+            if bodies.error.is_some() {
+                break;
             }
-            let mut iter = self.iter();
-            let block_header = self
-                .block_headers(block_height_range.end - 1..block_height_range.end)
-                .await;
-            // println!("build_blocks_backward {:#?}", block_height_range);
-            let header = block_header.map_err(|e| e.to_string()).unwrap(); // check the return
-            let res2: Vec<_> = header
-                .clone()
-                .into_iter()
-                .map(|bh| {
-                    return self.build_block_transactions(bh, block_height_range.end - 1);
-                })
-                .collect();
-            let mut res = futures::future::join_all(res2).await;
-            let d = res.remove(0);
-            if d.as_ref().ok().is_some() {
-                if iter.next().is_none() {
-                    return Ok(blocks);
+        }
+        let mut blocks = Vec::with_capacity(headers.len());
+        for (header, transactions) in headers.into_iter().zip(bodies.items) {
+            blocks.push(checked_block(header.block_height, header, transactions)?);
+        }
+        match bodies.error {
+            Some(e) => Err(e.into()),
+            None => Ok(blocks),
+        }
+    }
+}
+
+/// Split the range in consecutive batches of at most `batch_len` heights
+fn batches(block_height_range: Range<u32>, batch_len: u32) -> impl Iterator<Item = Range<u32>> {
+    let batch_len = batch_len.max(1);
+    let end = block_height_range.end;
+    block_height_range
+        .step_by(batch_len as usize)
+        .map(move |start| start..start.saturating_add(batch_len).min(end))
+}
+
+/// The items fetched from the start of a range, and the error that stopped the fetch
+struct Fetched<T> {
+    items: Vec<T>,
+    error: Option<ServerError>,
+}
+
+impl<T> Default for Fetched<T> {
+    fn default() -> Self {
+        Fetched {
+            items: vec![],
+            error: None,
+        }
+    }
+}
+
+impl<T> Fetched<T> {
+    /// Append the items fetched for the heights that follow, unless the fetch already stopped
+    fn extend(&mut self, next: Fetched<T>) {
+        if self.error.is_none() {
+            self.items.extend(next.items);
+            self.error = next.error;
+        }
+    }
+}
+
+impl<T> FromIterator<Result<T, ServerError>> for Fetched<T> {
+    fn from_iter<I: IntoIterator<Item = Result<T, ServerError>>>(iter: I) -> Self {
+        let mut fetched = Fetched::default();
+        for item in iter {
+            match item {
+                Ok(item) => fetched.items.push(item),
+                Err(e) => {
+                    fetched.error = Some(e);
+                    break;
                 }
-                let new_block = d.unwrap();
-                blocks.push(new_block);
-                return self
-                    .build_blocks_forward(
-                        blocks,
-                        block_height_range.start..block_height_range.end - 1,
-                    )
-                    .await;
             }
-            return Err(ServerError);
         }
-        .boxed()
+        fetched
     }
 }
 
-fn validate_block_transactions(transactions: Vec<Transaction>) -> Result<(), StateTransitionError> {
-    for transaction in transactions.iter() {
-        let validate = transaction.execute();
-        match validate {
-            Ok(()) => continue,
-            Err(_e) => return Err(StateTransitionError {}),
+fn fetch_headers<S: ServerAPI + ?Sized>(
+    server: &S,
+    block_height_range: Range<u32>,
+) -> BoxFuture<'_, Result<Vec<BlockHeader>, ServerError>> {
+    server.block_headers(block_height_range)
+}
+
+fn fetch_transactions<S: ServerAPI + ?Sized>(
+    server: &S,
+    block_height_range: Range<u32>,
+) -> BoxFuture<'_, Result<Vec<Vec<Transaction>>, ServerError>> {
+    server.block_transactions(block_height_range)
+}
+
+/// Fetch the items of the range until the first height that cannot be fetched
+async fn fetch_prefix<S, T>(
+    server: &S,
+    fetch: Fetch<S, T>,
+    block_height_range: Range<u32>,
+    config: &BuildConfig,
+) -> Fetched<T>
+where
+    S: Sync + ?Sized,
+    T: Send,
+{
+    let mode = FailureMode::FailFast;
+    let fetched = fetch_range(server, fetch, block_height_range, config, mode).await;
+    fetched.into_iter().collect()
+}
+
+/// Fetch the item of each height of the range, retrying and bisecting the failed requests
+///
+/// A request that still fails after its retries, or is answered with fewer
+/// items than heights, is split in two halves fetched one after the other,
+/// down to single heights. A bad height then only costs the requests to
+/// find it, and the items of the other heights are kept. With
+/// [`FailureMode::FailFast`], the fetch stops at the first height that
+/// cannot be fetched.
+fn fetch_range<'a, S, T>(
+    server: &'a S,
+    fetch: Fetch<S, T>,
+    block_height_range: Range<u32>,
+    config: &'a BuildConfig,
+    mode: FailureMode,
+) -> BoxFuture<'a, Vec<Result<T, ServerError>>>
+where
+    S: Sync + ?Sized,
+    T: Send + 'a,
+{
+    async move {
+        if block_height_range.is_empty() {
+            return vec![];
+        }
+        let error =
+            match fetch_with_retries(server, fetch, block_height_range.clone(), config).await {
+                Ok(items) => return items.into_iter().map(Ok).collect(),
+                Err(e) => e,
+            };
+        if block_height_range.len() == 1 {
+            return vec![Err(error)];
+        }
+        let middle = block_height_range.start + block_height_range.len() as u32 / 2;
+        let left = block_height_range.start..middle;
+        let mut fetched = fetch_range(server, fetch, left, config, mode).await;
+        if mode == FailureMode::CollectAll || fetched.iter().all(Result::is_ok) {
+            let right = middle..block_height_range.end;
+            fetched.extend(fetch_range(server, fetch, right, config, mode).await);
+        }
+        fetched
+    }
+    .boxed()
+}
+
+/// Send the request again while it fails with an error that may go away
+async fn fetch_with_retries<S: ?Sized, T>(
+    server: &S,
+    fetch: Fetch<S, T>,
+    block_height_range: Range<u32>,
+    config: &BuildConfig,
+) -> Result<Vec<T>, ServerError> {
+    let mut delay = config.retry_delay;
+    let mut attempt = 0;
+    loop {
+        let error = match fetch(server, block_height_range.clone()).await {
+            Ok(items) if items.len() == block_height_range.len() => return Ok(items),
+            Ok(items) if items.len() > block_height_range.len() => ServerError::Transport(format!(
+                "{} items answered for {} heights",
+                items.len(),
+                block_height_range.len()
+            )),
+            Ok(items) => ServerError::MissingHeight(block_height_range.start + items.len() as u32),
+            Err(e) => e,
+        };
+        let wait = match error {
+            ServerError::Throttled { retry_after_ms } => {
+                delay.max(Duration::from_millis(retry_after_ms))
+            }
+            ServerError::Unavailable | ServerError::Transport(_) => delay,
+            _ => return Err(error),
+        };
+        if attempt == config.retries {
+            return Err(error);
+        }
+        tokio::time::sleep(wait).await;
+        delay = delay.saturating_mul(2);
+        attempt += 1;
+    }
+}
+
+/// [`Blocks::build_blocks_sequential`], executing the blocks on `state` when given
+async fn build_sequential<S: ServerAPI + Sync + ?Sized>(
+    server: &S,
+    mut state: Option<&mut WorldState>,
+    block_height_range: Range<u32>,
+    config: BuildConfig,
+) -> Result<Vec<Block>, BuildError> {
+    let config = config.fail_fast();
+    let mut blocks: Vec<Block> = Vec::with_capacity(block_height_range.len());
+    for batch in batches(block_height_range, config.batch_len) {
+        let headers = fetch_prefix(server, fetch_headers, batch.clone(), &config).await;
+        let mut parent = blocks.last().map(|block| block.header.hash());
+        let linked = headers
+            .items
+            .iter()
+            .take_while(|header| {
+                let follows = parent.is_none_or(|hash| hash == header.parent_hash);
+                parent = Some(header.hash());
+                follows
+            })
+            .count();
+        let linked_headers = headers.items[..linked].iter().copied().map(Ok);
+        let linked_headers = verified_headers(batch.start, linked_headers);
+        let built = build_bodies(server, batch.start, linked_headers, true, &config).await;
+        for block in built {
+            let block = block?;
+            if let Some(state) = state.as_deref_mut() {
+                state.apply_block(&block)?;
+            }
+            blocks.push(block);
+        }
+        if linked < headers.items.len() {
+            let height = batch.start + linked as u32;
+            return Err(StateTransitionError::parent_mismatch(height).into());
+        }
+        if let Some(e) = headers.error {
+            return Err(e.into());
+        }
+    }
+    Ok(blocks)
+}
+
+/// [`Blocks::build_blocks_pipelined`], executing the blocks on `state` when given
+async fn build_pipelined<S: ServerAPI + Send + Sync + ?Sized + 'static>(
+    server: Arc<S>,
+    mut state: Option<&mut WorldState>,
+    block_height_range: Range<u32>,
+    config: BuildConfig,
+) -> Result<Vec<Block>, BuildError> {
+    let mut blocks: Vec<Block> = Vec::with_capacity(block_height_range.len());
+    let mut batches = batches(block_height_range, config.batch_len);
+    let prefetch = |batch: Range<u32>| {
+        let s = server.clone();
+        AbortOnDrop(tokio::spawn(async move {
+            prefetch_batch(&*s, batch, &config).await
+        }))
+    };
+    let mut window: VecDeque<_> = batches
+        .by_ref()
+        .take(config.concurrency.max(1))
+        .map(prefetch)
+        .collect();
+    while let Some(task) = window.pop_front() {
+        let appended = match task.await {
+            Ok(batch) => batch.append_to(&mut blocks, state.as_deref_mut()),
+            Err(e) => Err(BuildError::Task(e.to_string())),
+        };
+        // Returning drops the window, which aborts its tasks
+        appended?;
+        window.extend(batches.next().map(prefetch));
+    }
+    Ok(blocks)
+}
+
+/// The headers and transactions of a batch, fetched ahead of their execution
+struct PrefetchedBatch {
+    start: u32,
+    headers: Fetched<BlockHeader>,
+    /// The transactions of the headers before the first one failing verification.
+    bodies: Fetched<Vec<Transaction>>,
+}
+
+impl PrefetchedBatch {
+    /// Link the blocks of the batch to `blocks` and execute them in height order
+    ///
+    /// The blocks are applied to `state` when given.
+    fn append_to(
+        self,
+        blocks: &mut Vec<Block>,
+        mut state: Option<&mut WorldState>,
+    ) -> Result<(), BuildError> {
+        let verified = self.bodies.items.len();
+        let headers = self.headers.items.iter().zip(self.bodies.items);
+        for (height, (header, transactions)) in (self.start..).zip(headers) {
+            if blocks
+                .last()
+                .is_some_and(|parent| parent.header.hash() != header.parent_hash)
+            {
+                return Err(StateTransitionError::parent_mismatch(height).into());
+            }
+            let block = checked_block(height, *header, transactions)?;
+            if let Some(state) = state.as_deref_mut() {
+                state.apply_block(&block)?;
+            }
+            blocks.push(block);
+        }
+        let next = self.start + verified as u32;
+        if let Some(e) = self.bodies.error {
+            return Err(e.into());
+        }
+        if verified < self.headers.items.len() {
+            return Err(StateTransitionError::invalid_header(next).into());
+        }
+        match self.headers.error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Fetch the headers of the batch, then the transactions of the ones passing verification
+async fn prefetch_batch<S: ServerAPI + Sync + ?Sized>(
+    server: &S,
+    block_height_range: Range<u32>,
+    config: &BuildConfig,
+) -> PrefetchedBatch {
+    let start = block_height_range.start;
+    let headers = fetch_prefix(server, fetch_headers, block_height_range, config).await;
+    let verified = (start..)
+        .zip(&headers.items)
+        .take_while(|(height, header)| header.block_height == *height && header.verify());
+    let verified_end = start + verified.count() as u32;
+    let bodies = fetch_prefix(server, fetch_transactions, start..verified_end, config).await;
+    PrefetchedBatch {
+        start,
+        headers,
+        bodies,
+    }
+}
+
+/// A spawned task, aborted when it is dropped before finishing
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Future for AbortOnDrop<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Build the batch in its own task, each of its heights failing if the task panics
+///
+/// The task is aborted when the returned future is dropped.
+fn spawn_batch<S: ServerAPI + Send + Sync + ?Sized + 'static>(
+    server: &Arc<S>,
+    batch: Range<u32>,
+    config: BuildConfig,
+) -> impl Future<Output = (Range<u32>, Vec<Result<Block, BuildError>>)> {
+    let server = server.clone();
+    let task = AbortOnDrop(tokio::spawn({
+        let batch = batch.clone();
+        async move { build_batch(&*server, batch, &config).await }
+    }));
+    task.map(move |results| {
+        let results = results.unwrap_or_else(|e| {
+            let task_error = || Err(BuildError::Task(e.to_string()));
+            batch.clone().map(|_| task_error()).collect()
+        });
+        (batch, results)
+    })
+}
+
+/// Build the blocks of the range, fetching the headers then the transactions in one request each
+///
+/// The failed requests are retried and bisected, see [`fetch_range`]. The
+/// transactions are only requested for the headers passing verification,
+/// and each height of the range gets its block or its error. With
+/// [`FailureMode::FailFast`], the results stop at the first error.
+async fn build_batch<S: ServerAPI + Sync + ?Sized>(
+    server: &S,
+    block_height_range: Range<u32>,
+    config: &BuildConfig,
+) -> Vec<Result<Block, BuildError>> {
+    let start = block_height_range.start;
+    let mode = config.failure_mode;
+    let headers = fetch_range(server, fetch_headers, block_height_range, config, mode).await;
+    let headers = verified_headers(start, headers);
+    build_bodies(server, start, headers, true, config).await
+}
+
+/// The headers starting at `start`, failing at the ones not passing [`BlockHeader::verify`]
+///
+/// A header whose `block_height` is not its height fails as well.
+fn verified_headers<I>(start: u32, headers: I) -> Vec<Result<BlockHeader, BuildError>>
+where
+    I: IntoIterator<Item = Result<BlockHeader, ServerError>>,
+{
+    (start..)
+        .zip(headers)
+        .map(|(height, header)| match header {
+            Ok(header) if header.block_height == height && header.verify() => Ok(header),
+            Ok(_) => Err(StateTransitionError::invalid_header(height).into()),
+            Err(e) => Err(e.into()),
+        })
+        .collect()
+}
+
+/// Fetch the transactions of the headers starting at `start` and build their blocks
+///
+/// The transactions are requested for each run of consecutive headers, a
+/// failed header keeps its error. The transactions are executed when
+/// `execute` is set, otherwise they only have to match the root.
+async fn build_bodies<S: ServerAPI + Sync + ?Sized>(
+    server: &S,
+    start: u32,
+    headers: Vec<Result<BlockHeader, BuildError>>,
+    execute: bool,
+    config: &BuildConfig,
+) -> Vec<Result<Block, BuildError>> {
+    let mode = config.failure_mode;
+    let mut blocks = Vec::with_capacity(headers.len());
+    let mut headers = headers.into_iter().peekable();
+    while headers.peek().is_some() {
+        let run_start = start + blocks.len() as u32;
+        let mut run = vec![];
+        while let Some(Ok(header)) = headers.next_if(Result::is_ok) {
+            run.push(header);
+        }
+        let run_end = run_start + run.len() as u32;
+        let bodies = fetch_range(server, fetch_transactions, run_start..run_end, config, mode);
+        let run = (run_start..).zip(run).zip(bodies.await);
+        blocks.extend(run.map(
+            |((height, header), transactions)| -> Result<_, BuildError> {
+                let block = match execute {
+                    true => checked_block(height, header, transactions?)?,
+                    false => rooted_block(height, header, transactions?)?,
+                };
+                Ok(block)
+            },
+        ));
+        if let Some(Err(e)) = headers.next() {
+            blocks.push(Err(e));
+        }
+        if mode == FailureMode::FailFast {
+            if let Some(failed) = blocks.iter().position(Result::is_err) {
+                blocks.truncate(failed + 1);
+                break;
+            }
+        }
+    }
+    blocks
+}
+
+/// The block of `header`, once its transactions match the root
+fn rooted_block(
+    height: u32,
+    header: BlockHeader,
+    transactions: Vec<Transaction>,
+) -> Result<Block, StateTransitionError> {
+    if merkle_root(&transactions) != header.transactions_root {
+        return Err(StateTransitionError::transactions_root_mismatch(height));
+    }
+    Ok(Block {
+        header,
+        transactions,
+    })
+}
+
+/// The block of `header`, once its transactions match the root and execute
+fn checked_block(
+    height: u32,
+    header: BlockHeader,
+    transactions: Vec<Transaction>,
+) -> Result<Block, StateTransitionError> {
+    let block = rooted_block(height, header, transactions)?;
+    validate_block_transactions(height, &block.transactions)?;
+    Ok(block)
+}
+
+fn validate_block_transactions(
+    height: u32,
+    transactions: &[Transaction],
+) -> Result<(), StateTransitionError> {
+    for (index, transaction) in transactions.iter().enumerate() {
+        if let Err(reason) = transaction.execute() {
+            return Err(StateTransitionError::transaction(
+                height,
+                index,
+                transaction.tx_id,
+                reason,
+            ));
         }
     }
     Ok(())
@@ -234,33 +1188,892 @@ fn validate_block_transactions(transactions: Vec<Transaction>) -> Result<(), Sta
 mod tests {
 
     use super::*;
+    use crate::chaos::{ChaosConfig, ChaosServerApi, Latency};
+    use crate::disk_store::DiskBlockStore;
+    use crate::generator::{signing_key, ChainGenerator, GeneratorConfig, TxCount};
+    use crate::merkle::Hash;
+    use crate::store::BlockStore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::sync::watch;
 
     // #[test]
     #[tokio::test]
     // mock the server
     async fn server_api() {
+        let txns = vec![Transaction::sign(
+            TransactionFields::default(),
+            &signing_key(0, 0),
+        )];
+        let header = BlockHeader {
+            transactions_root: merkle_root(&txns),
+            ..Default::default()
+        };
+        let b = Block {
+            header,
+            transactions: txns.clone(),
+        };
+
         let mut server = MockBlockList::new();
-        let _ = server.expect_block_headers().returning(move |_| {
-            let header = BlockHeader::default();
-            Ok(vec![header])
+        let _ = server
+            .expect_block_headers()
+            .returning(move |_| Ok(vec![header]));
+        let _ = server
+            .expect_block_transactions()
+            .returning(move |_| Ok(vec![txns.clone()]));
+
+        let block = server.build_block_transactions(header, 0).await;
+        assert_eq!(block.clone().unwrap(), b.clone());
+        assert_eq!(block.unwrap().header.block_height, 0);
+
+        // The server returned other transactions than the ones of the header
+        let block = server
+            .build_block_transactions(BlockHeader::default(), 0)
+            .await;
+        assert!(matches!(
+            block,
+            Err(BuildError::StateTransition(StateTransitionError {
+                reason: StateTransitionReason::TransactionsRootMismatch,
+                ..
+            }))
+        ));
+
+        let server = Arc::new(server);
+        let blocks = server.clone().build_blocks_parallel(0..1).await;
+        assert_eq!(blocks.unwrap(), vec![b.clone()]);
+        let blocks = server.build_blocks_backward(vec![], 0..1).await;
+        assert_eq!(blocks.unwrap(), vec![b]);
+    }
+
+    #[tokio::test]
+    async fn builds_from_any_server_into_a_separate_sink() {
+        let (blocks, store) = generated_store(12);
+        let server: Arc<dyn ServerAPI + Send + Sync> = Arc::new(store.clone());
+        let mut built = Vec::new();
+        server.build_blocks_into(2..12, &mut built).await.unwrap();
+        assert_eq!(built, blocks[2..12]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut disk = DiskBlockStore::open(dir.path()).unwrap();
+        let slow = ChaosServerApi::new(
+            store.clone(),
+            ChaosConfig {
+                latency: Latency::Fixed(Duration::from_millis(1)),
+                ..Default::default()
+            },
+        );
+        Arc::new(slow)
+            .build_blocks_into(0..12, &mut disk)
+            .await
+            .unwrap();
+        assert_eq!(disk.get(11).unwrap(), Some(blocks[11].clone()));
+
+        // Corrupted bodies are caught by the builders
+        let corrupting = ChaosServerApi::new(
+            store,
+            ChaosConfig {
+                corrupt_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        let mut sink = BlockList::new();
+        let result = Arc::new(corrupting)
+            .build_blocks_into(0..12, &mut sink)
+            .await;
+        assert!(matches!(
+            result,
+            Err(BuildError::StateTransition(StateTransitionError {
+                reason: StateTransitionReason::TransactionsRootMismatch,
+                ..
+            }))
+        ));
+        assert!(sink.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_unsigned_transactions() {
+        let config = GeneratorConfig {
+            faults: [(2, StateTransitionReason::InvalidSignature)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let list: BlockList = ChainGenerator::new(config).take(4).collect();
+        let result = Arc::new(list).build_blocks_parallel(0..4).await;
+        assert!(matches!(
+            result,
+            Err(BuildError::StateTransition(StateTransitionError {
+                block_height: 2,
+                reason: StateTransitionReason::InvalidSignature,
+                ..
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_transactions_is_a_server_error() {
+        let list = BlockList::new();
+        let header = BlockHeader {
+            block_height: 3,
+            ..Default::default()
+        };
+        let block = list.build_block_transactions(header, 3).await;
+        assert!(matches!(block, Err(BuildError::Server(_))));
+    }
+
+    #[test]
+    fn state_transition_error_reports_the_failing_transaction() {
+        let header = StateTransitionError::invalid_header(7);
+        assert_eq!(
+            header.to_string(),
+            "State transition error at height 7: invalid header"
+        );
+
+        let tx = StateTransitionError::transaction(
+            7,
+            2,
+            TransactionId([0xab; 32]),
+            StateTransitionReason::BadNonce,
+        );
+        assert_eq!(tx.tx_index, Some(2));
+        assert_eq!(
+            tx.to_string(),
+            format!(
+                "State transition error at height 7: bad nonce (transaction 2 0x{})",
+                "ab".repeat(32)
+            )
+        );
+    }
+
+    fn generated_store(len: usize) -> (Vec<Block>, BlockStore) {
+        let blocks: Vec<Block> = ChainGenerator::new(GeneratorConfig::default())
+            .take(len)
+            .collect();
+        let store = blocks.iter().cloned().collect();
+        (blocks, store)
+    }
+
+    #[tokio::test]
+    async fn light_client_syncs_headers_and_fetches_bodies() {
+        let (blocks, store) = generated_store(300);
+        let mut client = LightClient::new(store.clone(), 0);
+        client.sync_headers(250).await.unwrap();
+        client.sync_headers(300).await.unwrap();
+        assert_eq!(client.headers().len(), 300);
+        assert_eq!(client.headers().tip(), Some(&blocks[299].header));
+
+        assert_eq!(client.blocks(120..123).await.unwrap(), blocks[120..123]);
+        assert!(matches!(
+            client.blocks(299..301).await,
+            Err(BuildError::Server(ServerError::MissingHeight(300)))
+        ));
+
+        // A body that does not match its synced header
+        let mut tampered = blocks[7].clone();
+        tampered.transactions.reverse();
+        tampered.transactions.push(blocks[8].transactions[0]);
+        store.insert(tampered);
+        match client.blocks(7..8).await {
+            Err(BuildError::StateTransition(e)) => assert_eq!(
+                (e.block_height, e.reason),
+                (7, StateTransitionReason::TransactionsRootMismatch)
+            ),
+            other => panic!("expected a root mismatch, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn light_client_rejects_broken_links() {
+        let (blocks, store) = generated_store(20);
+        let mut forged = blocks[12].clone();
+        forged.header.parent_hash = blocks[10].header.hash();
+        store.insert(forged);
+
+        let mut client = LightClient::new(store, 5);
+        match client.sync_headers(20).await {
+            Err(BuildError::StateTransition(e)) => assert_eq!(
+                (e.block_height, e.reason),
+                (12, StateTransitionReason::ParentMismatch)
+            ),
+            other => panic!("expected a parent mismatch, got {:?}", other),
+        }
+        // The headers verified before the broken link are kept
+        assert_eq!(client.headers().start(), 5);
+        assert_eq!(client.headers().end(), 12);
+
+        let mut headers = HeaderChain::new(0);
+        let mut unmined = blocks[0].header;
+        unmined.consensus_fields.difficulty = 200;
+        assert_eq!(
+            headers.push(unmined),
+            Err(StateTransitionError::invalid_header(0))
+        );
+        assert_eq!(
+            headers.push(blocks[1].header),
+            Err(StateTransitionError::invalid_header(0))
+        );
+    }
+
+    fn reason(result: Result<Vec<Block>, BuildError>) -> (u32, StateTransitionReason) {
+        match result {
+            Err(BuildError::StateTransition(e)) => (e.block_height, e.reason),
+            other => panic!("expected a state transition error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn dependent_builders_execute_the_blocks_on_the_world_state() {
+        for (height, fault) in [
+            (5, StateTransitionReason::InsufficientBalance),
+            (9, StateTransitionReason::BadNonce),
+        ] {
+            let config = GeneratorConfig {
+                faults: [(height, fault)].into_iter().collect(),
+                ..Default::default()
+            };
+            let generator = ChainGenerator::new(config);
+            let genesis = generator.genesis_state().clone();
+            let server = Arc::new(generator.take(20).collect::<BlockStore>());
+            let config = BuildConfig {
+                batch_len: 4,
+                ..Default::default()
+            };
+
+            // The blocks are valid on their own
+            assert!(server.build_blocks_sequential(0..20, config).await.is_ok());
+            let mut state = genesis.clone();
+            let built = server.build_blocks_sequential_on(&mut state, 0..20, config);
+            assert_eq!(reason(built.await), (height, fault));
+            let mut state = genesis.clone();
+            let built = server
+                .clone()
+                .build_blocks_pipelined_on(&mut state, 0..20, config);
+            assert_eq!(reason(built.await), (height, fault));
+        }
+
+        // Without faults, the state ends at the tip of the chain
+        let mut generator = ChainGenerator::new(GeneratorConfig::default());
+        let mut state = generator.genesis_state().clone();
+        let blocks: Vec<Block> = generator.by_ref().take(20).collect();
+        let server = Arc::new(blocks.iter().cloned().collect::<BlockStore>());
+        let built = server.build_blocks_pipelined_on(&mut state, 0..20, BuildConfig::default());
+        assert_eq!(built.await.unwrap(), blocks);
+        assert_eq!(&state, generator.state());
+    }
+
+    #[tokio::test]
+    async fn skips_verification_below_a_checkpoint() {
+        let config = GeneratorConfig {
+            faults: [
+                (2, StateTransitionReason::InvalidSignature),
+                (6, StateTransitionReason::InvalidSignature),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let blocks: Vec<Block> = ChainGenerator::new(config).take(8).collect();
+        let list: BlockList = blocks.iter().cloned().collect();
+        let checkpoints: Checkpoints = [(5, blocks[5].header.hash())].into_iter().collect();
+        let config = BuildConfig {
+            batch_len: 3,
+            ..Default::default()
+        };
+
+        // The invalid block 2 is trusted, the one at 6 is still executed
+        let built = list
+            .build_blocks_checkpointed(&checkpoints, 0..6, config)
+            .await;
+        assert_eq!(built.unwrap(), blocks[..6]);
+        assert_eq!(
+            reason(
+                list.build_blocks_checkpointed(&checkpoints, 0..8, config)
+                    .await
+            ),
+            (6, StateTransitionReason::InvalidSignature)
+        );
+        assert_eq!(
+            reason(
+                list.build_blocks_checkpointed(&Checkpoints::new(), 0..4, config)
+                    .await
+            ),
+            (2, StateTransitionReason::InvalidSignature)
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_headers_contradicting_checkpoints() {
+        let blocks: Vec<Block> = ChainGenerator::new(GeneratorConfig::default())
+            .take(8)
+            .collect();
+
+        let config = BuildConfig::default();
+        let list: BlockList = blocks.iter().cloned().collect();
+        let wrong: Checkpoints = [(3, blocks[4].header.hash())].into_iter().collect();
+        assert_eq!(
+            reason(list.build_blocks_checkpointed(&wrong, 0..8, config).await),
+            (3, StateTransitionReason::CheckpointMismatch)
+        );
+
+        // The chain has to reach the checkpoint
+        let mut forged = blocks.clone();
+        forged[2].header.parent_hash = Hash([7; 32]);
+        let list: BlockList = forged.into_iter().collect();
+        let checkpoints: Checkpoints = [(5, blocks[5].header.hash())].into_iter().collect();
+        assert_eq!(
+            reason(
+                list.build_blocks_checkpointed(&checkpoints, 0..8, config)
+                    .await
+            ),
+            (2, StateTransitionReason::ParentMismatch)
+        );
+        assert_eq!(checkpoints.trusted_end(4, &[]), Ok(4));
+    }
+
+    /// Counts the requests in flight at the same time
+    struct CountingServer {
+        inner: BlockStore,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        requests: AtomicUsize,
+        /// The highest end of a requested range.
+        highest: AtomicUsize,
+        /// The requests for this height wait until `true` is sent.
+        held: Option<(u32, watch::Receiver<bool>)>,
+    }
+
+    impl CountingServer {
+        fn new(inner: BlockStore) -> Arc<Self> {
+            Self::holding(inner, None)
+        }
+
+        fn holding(inner: BlockStore, held: Option<(u32, watch::Receiver<bool>)>) -> Arc<Self> {
+            Arc::new(CountingServer {
+                inner,
+                in_flight: AtomicUsize::new(0),
+                max_in_flight: AtomicUsize::new(0),
+                requests: AtomicUsize::new(0),
+                highest: AtomicUsize::new(0),
+                held,
+            })
+        }
+
+        async fn count<T>(&self, range: &Range<u32>, request: impl Future<Output = T>) -> T {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.highest.fetch_max(range.end as usize, Ordering::SeqCst);
+            match &self.held {
+                Some((height, released)) if range.contains(height) => {
+                    let mut released = released.clone();
+                    released.wait_for(|released| *released).await.unwrap();
+                }
+                _ => tokio::time::sleep(Duration::from_millis(2)).await,
+            }
+            let result = request.await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            result
+        }
+    }
+
+    #[async_trait]
+    impl ServerAPI for CountingServer {
+        async fn block_headers(
+            &self,
+            block_height_range: Range<u32>,
+        ) -> Result<Vec<BlockHeader>, ServerError> {
+            self.count(
+                &block_height_range,
+                self.inner.block_headers(block_height_range.clone()),
+            )
+            .await
+        }
+
+        async fn block_transactions(
+            &self,
+            block_height_range: Range<u32>,
+        ) -> Result<Vec<Vec<Transaction>>, ServerError> {
+            let request = self.inner.block_transactions(block_height_range.clone());
+            self.count(&block_height_range, request).await
+        }
+
+        async fn latest_height(&self) -> Result<u32, ServerError> {
+            self.inner.latest_height().await
+        }
+
+        async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+            self.inner.header_by_hash(hash).await
+        }
+
+        async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+            self.inner.block_by_hash(hash).await
+        }
+
+        async fn transaction_by_id(
+            &self,
+            tx_id: TransactionId,
+        ) -> Result<TransactionLocation, ServerError> {
+            self.inner.transaction_by_id(tx_id).await
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bounds_the_requests_in_flight() {
+        let (blocks, store) = generated_store(95);
+        let server = CountingServer::new(store);
+        let config = BuildConfig {
+            concurrency: 3,
+            batch_len: 10,
+            ..Default::default()
+        };
+        let built = server.clone().build_blocks_parallel_with(0..95, config);
+        assert_eq!(built.await.unwrap(), blocks);
+        assert!(server.max_in_flight.load(Ordering::SeqCst) <= 3);
+        // One request for the headers and one for the transactions of each batch
+        assert_eq!(server.requests.load(Ordering::SeqCst), 20);
+        assert_eq!(
+            batches(0..25, 10).collect::<Vec<_>>(),
+            vec![0..10, 10..20, 20..25]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_the_lowest_failing_height_of_a_batch() {
+        let config = GeneratorConfig {
+            faults: [
+                (6, StateTransitionReason::InvalidHeader),
+                (3, StateTransitionReason::InvalidSignature),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let store: BlockStore = ChainGenerator::new(config).take(10).collect();
+        let store = Arc::new(store);
+        let config = BuildConfig {
+            concurrency: 2,
+            batch_len: 8,
+            ..Default::default()
+        };
+        assert_eq!(
+            reason(
+                store
+                    .clone()
+                    .build_blocks_parallel_with(0..10, config)
+                    .await
+            ),
+            (3, StateTransitionReason::InvalidSignature)
+        );
+        assert_eq!(
+            reason(
+                store
+                    .clone()
+                    .build_blocks_parallel_with(4..10, config)
+                    .await
+            ),
+            (6, StateTransitionReason::InvalidHeader)
+        );
+        assert!(matches!(
+            store.build_blocks_parallel_with(8..12, config).await,
+            Err(BuildError::Server(ServerError::MissingHeight(10)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn retries_and_bisects_failed_requests() {
+        let (blocks, store) = generated_store(40);
+        let mut server = MockServerAPI::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let headers = (store.clone(), calls.clone());
+        server.expect_block_headers().returning(move |range| {
+            match headers.1.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(ServerError::Throttled { retry_after_ms: 1 }),
+                _ => futures::executor::block_on(headers.0.block_headers(range)),
+            }
         });
-        let _ = server.expect_block_transactions().returning(move |_| {
-            let txns = vec![Transaction::default()];
-            Ok(vec![txns])
+        // Height 13 is dropped from longer answers, 27 can never be fetched
+        server.expect_block_transactions().returning(move |range| {
+            let mut transactions =
+                futures::executor::block_on(store.block_transactions(range.clone()))?;
+            if range.contains(&27) {
+                return Err(ServerError::Unavailable);
+            }
+            if range.contains(&13) && range.len() > 1 {
+                transactions.pop();
+            }
+            Ok(transactions)
         });
+        let config = BuildConfig {
+            batch_len: 40,
+            retries: 1,
+            retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
 
-        let mut list = BlockList::new();
-        let txns = vec![Transaction::default()];
-        let b = Block {
-            header: BlockHeader::default(),
-            transactions: txns,
+        let transactions: Vec<_> = blocks.iter().map(|b| b.transactions.clone()).collect();
+        let fetched = fetch_prefix(&server, fetch_transactions, 0..40, &config).await;
+        assert_eq!(fetched.items, transactions[..27]);
+        assert_eq!(fetched.error, Some(ServerError::Unavailable));
+        let mode = FailureMode::CollectAll;
+        let fetched = fetch_range(&server, fetch_transactions, 0..40, &config, mode).await;
+        for (height, fetched) in fetched.into_iter().enumerate() {
+            match height {
+                27 => assert_eq!(fetched, Err(ServerError::Unavailable)),
+                _ => assert_eq!(fetched, Ok(transactions[height].clone())),
+            }
+        }
+
+        let server = Arc::new(server);
+        let built = server.clone().build_blocks_parallel_with(0..27, config);
+        assert_eq!(built.await.unwrap(), blocks[..27]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(matches!(
+            server.build_blocks_parallel_with(20..40, config).await,
+            Err(BuildError::Server(ServerError::Unavailable))
+        ));
+    }
+
+    #[tokio::test]
+    async fn collects_the_failure_of_every_height() {
+        let config = GeneratorConfig {
+            faults: [
+                (3, StateTransitionReason::InvalidSignature),
+                (6, StateTransitionReason::InvalidHeader),
+                (11, StateTransitionReason::TransactionsRootMismatch),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let blocks: Vec<Block> = ChainGenerator::new(config).take(20).collect();
+        let store: BlockStore = blocks.iter().cloned().collect();
+        let mut server = MockServerAPI::new();
+        let headers = store.clone();
+        server
+            .expect_block_headers()
+            .returning(move |range| futures::executor::block_on(headers.block_headers(range)));
+        // The transactions of height 14 can never be fetched
+        server.expect_block_transactions().returning(move |range| {
+            if range.contains(&14) {
+                return Err(ServerError::Unavailable);
+            }
+            futures::executor::block_on(store.block_transactions(range))
+        });
+        let server = Arc::new(server);
+        let config = BuildConfig {
+            concurrency: 2,
+            batch_len: 8,
+            retries: 0,
+            failure_mode: FailureMode::CollectAll,
+            ..Default::default()
         };
-        list.insert_at_tail(b.clone());
 
-        let block = list
-            .build_block_transactions(BlockHeader::default(), 0)
+        let report = server.clone().build_blocks_report(0..22, config).await;
+        assert!(!report.is_success());
+        let failed = [3, 6, 11, 14, 20, 21];
+        let built: Vec<_> = blocks
+            .iter()
+            .filter(|block| !failed.contains(&block.header.block_height))
+            .cloned()
+            .collect();
+        assert_eq!(report.blocks, built);
+        assert_eq!(report.failures.keys().copied().collect::<Vec<_>>(), failed);
+        let reasons = report.failures.values().take(3).cloned();
+        let reasons: Vec<_> = reasons.map(|e| reason(Err(e))).collect();
+        assert_eq!(
+            reasons,
+            [
+                (3, StateTransitionReason::InvalidSignature),
+                (6, StateTransitionReason::InvalidHeader),
+                (11, StateTransitionReason::TransactionsRootMismatch),
+            ]
+        );
+        assert!(matches!(
+            report.failures[&14],
+            BuildError::Server(ServerError::Unavailable)
+        ));
+        assert!(matches!(
+            report.failures[&21],
+            BuildError::Server(ServerError::MissingHeight(21))
+        ));
+        assert_eq!(
+            reason(report.into_result()),
+            (3, StateTransitionReason::InvalidSignature)
+        );
+
+        let config = BuildConfig {
+            failure_mode: FailureMode::FailFast,
+            ..config
+        };
+        let report = server.clone().build_blocks_report(0..22, config).await;
+        assert_eq!(report.blocks, blocks[..3]);
+        assert_eq!(report.failures.keys().copied().collect::<Vec<_>>(), [3]);
+    }
+
+    #[tokio::test]
+    async fn server_errors_do_not_panic() {
+        let (blocks, _) = generated_store(1);
+        let mut server = MockServerAPI::new();
+        server
+            .expect_block_transactions()
+            .returning(|_| Err(ServerError::Transport("connection reset".to_string())));
+        assert!(matches!(
+            server.build_block_transactions(blocks[0].header, 0).await,
+            Err(BuildError::Server(ServerError::Transport(_)))
+        ));
+    }
+
+    /// A server answering every range with the blocks starting at `shift`, and `extra` more
+    fn misplaced_server(store: BlockStore, shift: u32, extra: u32) -> Arc<MockServerAPI> {
+        let mut server = MockServerAPI::new();
+        let headers = store.clone();
+        server.expect_block_headers().returning(move |range| {
+            let range = shift..shift + range.len() as u32 + extra;
+            futures::executor::block_on(headers.block_headers(range))
+        });
+        server.expect_block_transactions().returning(move |range| {
+            let range = shift..shift + range.len() as u32 + extra;
+            futures::executor::block_on(store.block_transactions(range))
+        });
+        Arc::new(server)
+    }
+
+    #[tokio::test]
+    async fn rejects_blocks_answered_at_other_heights() {
+        let (_, store) = generated_store(20);
+        let server = misplaced_server(store.clone(), 0, 0);
+        let config = BuildConfig {
+            batch_len: 5,
+            retries: 0,
+            ..Default::default()
+        };
+        let built = [
+            server
+                .clone()
+                .build_blocks_parallel_with(10..15, config)
+                .await,
+            server.clone().build_blocks_pipelined(10..15, config).await,
+            server.build_blocks_sequential(10..15, config).await,
+            server
+                .build_blocks_checkpointed(&Checkpoints::new(), 10..15, config)
+                .await,
+        ];
+        for result in built {
+            assert!(matches!(
+                result,
+                Err(BuildError::StateTransition(e)) if e == StateTransitionError::invalid_header(10)
+            ));
+        }
+
+        // Longer answers are not cut down to the range
+        let server = misplaced_server(store, 10, 1);
+        assert!(matches!(
+            server.build_blocks_parallel_with(10..15, config).await,
+            Err(BuildError::Server(ServerError::Transport(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn streams_blocks_in_height_order_or_as_they_are_built() {
+        let (blocks, store) = generated_store(16);
+        let config = BuildConfig {
+            concurrency: 4,
+            batch_len: 4,
+            ..Default::default()
+        };
+
+        let server = CountingServer::new(store.clone());
+        let ordered = server.build_blocks_stream(0..16, config, StreamOrder::Ordered);
+        let ordered: Result<Vec<_>, _> = ordered.collect::<Vec<_>>().await.into_iter().collect();
+        assert_eq!(ordered.unwrap(), blocks);
+
+        // The first batch is only built once the others are yielded
+        let (release, released) = watch::channel(false);
+        let server = CountingServer::holding(store, Some((0, released)));
+        let mut unordered =
+            server
+                .clone()
+                .build_blocks_stream(0..16, config, StreamOrder::Unordered);
+        let mut built = vec![];
+        for _ in 0..12 {
+            built.push(unordered.next().await.unwrap().unwrap());
+        }
+        assert!(built.iter().all(|block| block.header.block_height >= 4));
+        release.send(true).unwrap();
+        while let Some(block) = unordered.next().await {
+            built.push(block.unwrap());
+        }
+        built.sort_by_key(|block| block.header.block_height);
+        assert_eq!(built, blocks);
+        assert!(server.max_in_flight.load(Ordering::SeqCst) <= 4);
+    }
+
+    #[tokio::test]
+    async fn stream_ends_after_the_first_failure() {
+        let (blocks, store) = generated_store(10);
+        let store = Arc::new(store);
+        let config = BuildConfig {
+            batch_len: 4,
+            retries: 0,
+            ..Default::default()
+        };
+
+        let built: Vec<_> = store
+            .clone()
+            .build_blocks_stream(0..16, config, StreamOrder::Ordered)
+            .collect()
             .await;
-        assert_eq!(block.clone().unwrap(), b.clone());
-        assert_eq!(block.unwrap().header.block_height, 0);
+        assert_eq!(built.len(), 11);
+        for (block, built) in blocks.iter().zip(&built) {
+            assert_eq!(built.as_ref().unwrap(), block);
+        }
+        assert!(matches!(
+            built[10],
+            Err(BuildError::Server(ServerError::MissingHeight(10)))
+        ));
+
+        let config = BuildConfig {
+            failure_mode: FailureMode::CollectAll,
+            ..config
+        };
+        let built: Vec<_> = store
+            .build_blocks_stream(0..16, config, StreamOrder::Ordered)
+            .collect()
+            .await;
+        assert_eq!(built.len(), 16);
+        assert!(built[10..].iter().all(Result::is_err));
+    }
+
+    #[test]
+    fn builds_long_chains_on_a_small_stack() {
+        let config = GeneratorConfig {
+            tx_count: TxCount::Fixed(0),
+            ..Default::default()
+        };
+        let store: BlockStore = ChainGenerator::new(config).take(20_000).collect();
+        let built = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()
+                    .unwrap();
+                runtime.block_on(store.build_blocks_backward(vec![], 0..20_000))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(built.unwrap().len(), 20_000);
+    }
+
+    #[tokio::test]
+    async fn sequential_blocks_follow_their_parent() {
+        let (blocks, store) = generated_store(12);
+        let mut forged = blocks[7].clone();
+        forged.header.parent_hash = blocks[5].header.hash();
+        store.insert(forged);
+        let config = BuildConfig {
+            batch_len: 4,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            reason(store.build_blocks_sequential(0..12, config).await),
+            (7, StateTransitionReason::ParentMismatch)
+        );
+        // Nothing below the start of the range is fetched or checked
+        let built = store.build_blocks_sequential(8..12, config).await;
+        assert_eq!(built.unwrap(), blocks[8..12]);
+
+        let forward = store.build_blocks_forward(vec![], 0..7).await.unwrap();
+        let heights: Vec<_> = forward.iter().map(|b| b.header.block_height).collect();
+        assert_eq!(heights, vec![6, 5, 4, 3, 2, 1, 0]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pipelines_fetching_and_executes_in_order() {
+        let (blocks, store) = generated_store(60);
+        let server = CountingServer::new(store);
+        let config = BuildConfig {
+            concurrency: 4,
+            batch_len: 5,
+            ..Default::default()
+        };
+        let built = server.clone().build_blocks_pipelined(0..60, config).await;
+        assert_eq!(built.unwrap(), blocks);
+        assert!(server.max_in_flight.load(Ordering::SeqCst) > 1);
+        assert!(server.max_in_flight.load(Ordering::SeqCst) <= 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pipeline_stops_at_the_first_failure() {
+        let config = GeneratorConfig {
+            faults: [
+                (23, StateTransitionReason::InvalidSignature),
+                (41, StateTransitionReason::InvalidHeader),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let store: BlockStore = ChainGenerator::new(config).take(400).collect();
+        let server = CountingServer::new(store);
+        let config = BuildConfig {
+            concurrency: 3,
+            batch_len: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            reason(server.clone().build_blocks_pipelined(0..400, config).await),
+            (23, StateTransitionReason::InvalidSignature)
+        );
+        // The window after the failing batch is aborted
+        let requests = server.requests.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(server.requests.load(Ordering::SeqCst), requests);
+        assert!(server.highest.load(Ordering::SeqCst) <= 25 + 3 * 5);
+
+        assert_eq!(
+            reason(server.build_blocks_pipelined(30..400, config).await),
+            (41, StateTransitionReason::InvalidHeader)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn parallel_builders_abort_their_batches_at_the_first_failure() {
+        let config = GeneratorConfig {
+            faults: [(23, StateTransitionReason::InvalidSignature)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let store: BlockStore = ChainGenerator::new(config).take(400).collect();
+        let config = BuildConfig {
+            concurrency: 3,
+            batch_len: 5,
+            ..Default::default()
+        };
+        let stops = |server: Arc<CountingServer>| async move {
+            let requests = server.requests.load(Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            assert_eq!(server.requests.load(Ordering::SeqCst), requests);
+            assert!(server.highest.load(Ordering::SeqCst) <= 25 + 3 * 5);
+        };
+
+        let server = CountingServer::new(store.clone());
+        let report = server.clone().build_blocks_report(0..400, config).await;
+        assert_eq!(report.blocks.len(), 23);
+        stops(server).await;
+
+        let server = CountingServer::new(store);
+        let mut stream = server
+            .clone()
+            .build_blocks_stream(0..400, config, StreamOrder::Ordered);
+        for _ in 0..23 {
+            assert!(stream.next().await.unwrap().is_ok());
+        }
+        assert!(stream.next().await.unwrap().is_err());
+        drop(stream);
+        stops(server).await;
     }
 }
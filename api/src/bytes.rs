@@ -0,0 +1,174 @@
+use std::fmt;
+
+/// The error returned when a string is not a `0x`-prefixed hex string of the right length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseBytesError {
+    MissingPrefix,
+    /// Number of bytes expected and found.
+    InvalidLength {
+        expected: usize,
+        found: usize,
+    },
+    InvalidCharacter {
+        index: usize,
+    },
+}
+
+impl fmt::Display for ParseBytesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseBytesError::MissingPrefix => write!(f, "Parse error: missing 0x prefix"),
+            ParseBytesError::InvalidLength { expected, found } => write!(
+                f,
+                "Parse error: expected {} bytes, found {}",
+                expected, found
+            ),
+            ParseBytesError::InvalidCharacter { index } => {
+                write!(f, "Parse error: invalid hex character at {}", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseBytesError {}
+
+/// Parse a `0x`-prefixed hex string into exactly `N` bytes
+pub fn parse_hex<const N: usize>(s: &str) -> Result<[u8; N], ParseBytesError> {
+    let digits = s.strip_prefix("0x").ok_or(ParseBytesError::MissingPrefix)?;
+    if digits.len() != 2 * N {
+        return Err(ParseBytesError::InvalidLength {
+            expected: N,
+            found: digits.len() / 2,
+        });
+    }
+    let mut bytes = [0; N];
+    hex::decode_to_slice(digits, &mut bytes).map_err(|e| match e {
+        hex::FromHexError::InvalidHexCharacter { index, .. } => {
+            ParseBytesError::InvalidCharacter { index: index + 2 }
+        }
+        _ => ParseBytesError::InvalidLength {
+            expected: N,
+            found: digits.len() / 2,
+        },
+    })?;
+    Ok(bytes)
+}
+
+///
+/// Define a newtype over `[u8; $len]` written as a `0x`-prefixed hex string
+///
+/// The string form is used by `Display`, `FromStr` and serde, so the JSON
+/// API shows `"0x1e6f…"` rather than an array of numbers.
+macro_rules! hex_bytes {
+    ($(#[$meta:meta])* $name:ident, $len:expr) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub [u8; $len]);
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name([0; $len])
+            }
+        }
+
+        impl From<[u8; $len]> for $name {
+            fn from(bytes: [u8; $len]) -> Self {
+                $name(bytes)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "0x{}", hex::encode(self.0))
+            }
+        }
+
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{}({})", stringify!($name), self)
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = $crate::bytes::ParseBytesError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $crate::bytes::parse_hex(s).map($name)
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+
+        impl $crate::codec::Encode for $name {
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.0);
+            }
+        }
+
+        impl $crate::codec::Decode for $name {
+            const MIN_LEN: usize = $len;
+
+            fn decode_from(
+                reader: &mut $crate::codec::Reader,
+            ) -> Result<Self, $crate::codec::DecodeError> {
+                reader.array().map($name)
+            }
+        }
+    };
+}
+
+pub(crate) use hex_bytes;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    hex_bytes!(Id, 4);
+
+    #[test]
+    fn parses_and_prints_prefixed_hex() {
+        let id: Id = "0x0a1B2c3d".parse().unwrap();
+        assert_eq!(id, Id([0x0a, 0x1b, 0x2c, 0x3d]));
+        assert_eq!(id.to_string(), "0x0a1b2c3d");
+        assert_eq!(format!("{:?}", id), "Id(0x0a1b2c3d)");
+        assert_eq!(serde_json::to_string(&id).unwrap(), "\"0x0a1b2c3d\"");
+        assert_eq!(serde_json::from_str::<Id>("\"0x0a1b2c3d\"").unwrap(), id);
+    }
+
+    #[test]
+    fn rejects_malformed_strings() {
+        assert_eq!(
+            "0a1b2c3d".parse::<Id>(),
+            Err(ParseBytesError::MissingPrefix)
+        );
+        assert_eq!(
+            "0x0a1b2c".parse::<Id>(),
+            Err(ParseBytesError::InvalidLength {
+                expected: 4,
+                found: 3
+            })
+        );
+        assert_eq!(
+            "0x0a1b2c3g".parse::<Id>(),
+            Err(ParseBytesError::InvalidCharacter { index: 9 })
+        );
+        assert!(serde_json::from_str::<Id>("[10, 27, 44, 61]").is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use crate::server::*;
+use async_trait::async_trait;
+use core::ops::Range;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The eviction policy of [`CachedServerApi`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheConfig {
+    /// Heights kept per kind of data, the least recently used are evicted first.
+    pub max_entries: usize,
+    /// Entries older than this are fetched again.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_entries: 100_000,
+            ttl: None,
+        }
+    }
+}
+
+/// What the cache saved, counted in heights except for `fetches`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    /// Calls made to the wrapped server.
+    pub fetches: u64,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    value: T,
+    inserted_at: Instant,
+    last_used: u64,
+}
+
+/// Entries of one kind of data with their recency order.
+#[derive(Debug)]
+struct Cache<T> {
+    entries: BTreeMap<u32, Entry<T>>,
+    /// Height of each entry by its last use.
+    recency: BTreeMap<u64, u32>,
+    clock: u64,
+}
+
+impl<T: Clone> Cache<T> {
+    fn new() -> Self {
+        Cache {
+            entries: BTreeMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// The cached values of the range by height, the heights not in it are to fetch
+    ///
+    /// Only the cached heights of the range are visited, so a long range
+    /// costs no more than the entries it contains.
+    fn lookup(
+        &mut self,
+        block_height_range: Range<u32>,
+        config: &CacheConfig,
+        stats: &mut CacheStats,
+    ) -> BTreeMap<u32, T> {
+        let now = Instant::now();
+        let cached: Vec<u32> = self
+            .entries
+            .range(block_height_range.clone())
+            .map(|(&height, _)| height)
+            .collect();
+        let mut values = BTreeMap::new();
+        for height in cached {
+            let entry = &self.entries[&height];
+            if config
+                .ttl
+                .is_some_and(|ttl| now.duration_since(entry.inserted_at) > ttl)
+            {
+                self.remove(height);
+                stats.expirations += 1;
+                continue;
+            }
+            self.clock += 1;
+            let entry = self.entries.get_mut(&height).unwrap();
+            self.recency.remove(&entry.last_used);
+            self.recency.insert(self.clock, height);
+            entry.last_used = self.clock;
+            values.insert(height, entry.value.clone());
+        }
+        stats.hits += values.len() as u64;
+        stats.misses += (block_height_range.len() - values.len()) as u64;
+        values
+    }
+
+    fn insert(&mut self, height: u32, value: T, config: &CacheConfig, stats: &mut CacheStats) {
+        if config.max_entries == 0 {
+            return;
+        }
+        self.remove(height);
+        while self.entries.len() >= config.max_entries {
+            let (_, oldest) = self.recency.pop_first().unwrap();
+            self.entries.remove(&oldest);
+            stats.evictions += 1;
+        }
+        self.clock += 1;
+        self.recency.insert(self.clock, height);
+        self.entries.insert(
+            height,
+            Entry {
+                value,
+                inserted_at: Instant::now(),
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn remove(&mut self, height: u32) {
+        if let Some(entry) = self.entries.remove(&height) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    headers: Cache<BlockHeader>,
+    transactions: Cache<Vec<Transaction>>,
+    stats: CacheStats,
+}
+
+/// The runs of consecutive heights of the range that are not cached
+fn missing_ranges<T>(block_height_range: Range<u32>, values: &BTreeMap<u32, T>) -> Vec<Range<u32>> {
+    let mut ranges = vec![];
+    let mut next = block_height_range.start;
+    for &height in values.keys() {
+        if next < height {
+            ranges.push(next..height);
+        }
+        next = height + 1;
+    }
+    if next < block_height_range.end {
+        ranges.push(next..block_height_range.end);
+    }
+    ranges
+}
+
+///
+/// `ServerAPI` decorator memoising headers and transactions by height
+///
+/// A range query is answered from the cache, and only the runs of missing
+/// heights are requested from the wrapped server, each run in one call. The
+/// tip height and the lookups by hash and by id go to the wrapped server.
+#[derive(Debug)]
+pub struct CachedServerApi<S> {
+    inner: S,
+    config: CacheConfig,
+    state: Mutex<State>,
+}
+
+impl<S: ServerAPI> CachedServerApi<S> {
+    pub fn new(inner: S, config: CacheConfig) -> Self {
+        CachedServerApi {
+            inner,
+            config,
+            state: Mutex::new(State {
+                headers: Cache::new(),
+                transactions: Cache::new(),
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Drop every cached entry, e.g. after a reorganisation of the chain
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.headers = Cache::new();
+        state.transactions = Cache::new();
+    }
+}
+
+impl<S: ServerAPI + Send + Sync> CachedServerApi<S> {
+    /// Answer the range from `cache`, fetching the missing runs with `fetch`
+    async fn cached<T, F, Fut>(
+        &self,
+        block_height_range: Range<u32>,
+        cache: impl Fn(&mut State) -> (&mut Cache<T>, &mut CacheStats),
+        fetch: F,
+    ) -> Result<Vec<T>, ServerError>
+    where
+        T: Clone,
+        F: Fn(Range<u32>) -> Fut,
+        Fut: std::future::Future<Output = Result<Vec<T>, ServerError>>,
+    {
+        if block_height_range.start > block_height_range.end {
+            return fetch(block_height_range).await;
+        }
+        let mut values = {
+            let mut state = self.state.lock().unwrap();
+            let (cache, stats) = cache(&mut state);
+            cache.lookup(block_height_range.clone(), &self.config, stats)
+        };
+        for missing in missing_ranges(block_height_range, &values) {
+            self.state.lock().unwrap().stats.fetches += 1;
+            let fetched = fetch(missing.clone()).await?;
+            if fetched.len() != missing.len() {
+                // Never cache nor return a partial answer
+                return Err(ServerError::MissingHeight(
+                    missing.start + fetched.len() as u32,
+                ));
+            }
+            let mut state = self.state.lock().unwrap();
+            let (cache, stats) = cache(&mut state);
+            for (height, value) in missing.zip(fetched) {
+                cache.insert(height, value.clone(), &self.config, stats);
+                values.insert(height, value);
+            }
+        }
+        Ok(values.into_values().collect())
+    }
+}
+
+#[async_trait]
+impl<S: ServerAPI + Send + Sync> ServerAPI for CachedServerApi<S> {
+    async fn block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<BlockHeader>, ServerError> {
+        self.cached(
+            block_height_range,
+            |state| (&mut state.headers, &mut state.stats),
+            |range| self.inner.block_headers(range),
+        )
+        .await
+    }
+
+    async fn block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<Vec<Transaction>>, ServerError> {
+        self.cached(
+            block_height_range,
+            |state| (&mut state.transactions, &mut state.stats),
+            |range| self.inner.block_transactions(range),
+        )
+        .await
+    }
+
+    async fn latest_height(&self) -> Result<u32, ServerError> {
+        self.inner.latest_height().await
+    }
+
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+        self.inner.header_by_hash(hash).await
+    }
+
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+        self.inner.block_by_hash(hash).await
+    }
+
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError> {
+        self.inner.transaction_by_id(tx_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::BlockStore;
+    use mockall::predicate::eq;
+
+    fn headers(range: Range<u32>) -> Vec<BlockHeader> {
+        range
+            .map(|height| BlockHeader {
+                block_height: height,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn store(len: u32) -> BlockStore {
+        headers(0..len)
+            .into_iter()
+            .map(|header| Block {
+                header,
+                transactions: vec![],
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetches_only_the_missing_heights() {
+        let mut server = MockServerAPI::new();
+        for range in [0..10, 10..15, 20..25, 15..20] {
+            server
+                .expect_block_headers()
+                .with(eq(range))
+                .times(1)
+                .returning(|range| Ok(headers(range)));
+        }
+        let cached = CachedServerApi::new(server, CacheConfig::default());
+
+        assert_eq!(cached.block_headers(0..10).await.unwrap(), headers(0..10));
+        assert_eq!(cached.block_headers(5..15).await.unwrap(), headers(5..15));
+        assert_eq!(cached.block_headers(20..25).await.unwrap(), headers(20..25));
+        assert_eq!(cached.block_headers(0..25).await.unwrap(), headers(0..25));
+        assert_eq!(
+            cached.stats(),
+            CacheStats {
+                hits: 25,
+                misses: 25,
+                evictions: 0,
+                expirations: 0,
+                fetches: 4,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn evicts_the_least_recently_used_heights() {
+        let config = CacheConfig {
+            max_entries: 3,
+            ttl: None,
+        };
+        let cached = CachedServerApi::new(store(10), config);
+        cached.block_transactions(0..3).await.unwrap();
+        // 0 is used again, so 1 is the least recently used when 3 comes in
+        cached.block_transactions(0..1).await.unwrap();
+        cached.block_transactions(3..4).await.unwrap();
+        cached.block_transactions(0..1).await.unwrap();
+        assert_eq!(cached.stats().evictions, 1);
+        assert_eq!(cached.stats().fetches, 2);
+        cached.block_transactions(1..2).await.unwrap();
+        assert_eq!(cached.stats().fetches, 3);
+    }
+
+    #[tokio::test]
+    async fn fetches_expired_heights_again() {
+        let config = CacheConfig {
+            max_entries: 10,
+            ttl: Some(Duration::from_millis(20)),
+        };
+        let cached = CachedServerApi::new(store(10), config);
+        cached.block_headers(0..5).await.unwrap();
+        cached.block_headers(0..5).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        cached.block_headers(0..5).await.unwrap();
+        let stats = cached.stats();
+        assert_eq!((stats.hits, stats.expirations, stats.fetches), (5, 5, 2));
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_partial_answers() {
+        let mut server = MockServerAPI::new();
+        server
+            .expect_block_headers()
+            .times(2)
+            .returning(|_| Ok(headers(0..2)));
+        let cached = CachedServerApi::new(server, CacheConfig::default());
+        for _ in 0..2 {
+            assert_eq!(
+                cached.block_headers(0..4).await,
+                Err(ServerError::MissingHeight(2))
+            );
+        }
+        assert_eq!(cached.stats().hits, 0);
+    }
+
+    #[tokio::test]
+    async fn forwards_long_ranges_without_visiting_every_height() {
+        let cached = CachedServerApi::new(store(10), CacheConfig::default());
+        assert_eq!(cached.block_headers(2..8).await.unwrap(), headers(2..8));
+        assert_eq!(
+            cached.block_headers(0..u32::MAX).await,
+            Err(ServerError::MissingHeight(10))
+        );
+        let stats = cached.stats();
+        assert_eq!((stats.hits, stats.fetches), (6, 3));
+        assert_eq!(cached.block_headers(0..10).await.unwrap(), headers(0..10));
+    }
+}
@@ -0,0 +1,500 @@
+use crate::merkle::merkle_root;
+use crate::server::*;
+use crate::state::{BlockUndo, WorldState};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use tokio::sync::broadcast;
+
+/// The rule choosing the canonical branch among the branches of a [`BlockTree`].
+///
+/// The canonical branch is the one with the largest total weight, the first
+/// branch seen wins a tie.
+pub trait ForkChoice {
+    /// Weight the block adds to its branch
+    fn weight(&self, header: &BlockHeader) -> u128;
+}
+
+/// Every block weighs the same, the highest branch wins.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LongestChain;
+
+impl ForkChoice for LongestChain {
+    fn weight(&self, _header: &BlockHeader) -> u128 {
+        1
+    }
+}
+
+/// Blocks weigh their work, the branch that took the most work to mine wins.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeaviestChain;
+
+impl ForkChoice for HeaviestChain {
+    fn weight(&self, header: &BlockHeader) -> u128 {
+        header.work()
+    }
+}
+
+/// The change of canonical branch, sent to the subscribers of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reorg {
+    /// Last block shared by both branches.
+    pub fork_point: BlockHash,
+    /// Blocks removed from the canonical chain, from the highest to the lowest.
+    pub detached: Vec<Block>,
+    /// Blocks added to the canonical chain, from the lowest to the highest.
+    pub attached: Vec<Block>,
+}
+
+/// What inserting a block changed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertOutcome {
+    /// The block was already in the tree.
+    AlreadyKnown,
+    /// The block extends the canonical chain.
+    Extended,
+    /// The block is stored on a branch that is not canonical.
+    SideBranch,
+    /// The block made its branch canonical.
+    Reorged(Reorg),
+}
+
+/// The error returned when a block cannot be inserted.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub enum ChainError {
+    /// The parent of the block is not in the tree.
+    UnknownParent(BlockHash),
+    /// The block descends from a block that was rejected.
+    InvalidAncestor(BlockHash),
+    StateTransition(StateTransitionError),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainError::UnknownParent(hash) => {
+                write!(f, "Chain error: unknown parent {}", hash)
+            }
+            ChainError::InvalidAncestor(hash) => {
+                write!(f, "Chain error: invalid ancestor {}", hash)
+            }
+            ChainError::StateTransition(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<StateTransitionError> for ChainError {
+    fn from(e: StateTransitionError) -> Self {
+        ChainError::StateTransition(e)
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    block: Block,
+    /// Weight of the branch from the genesis block to this block included.
+    total_weight: u128,
+    /// Set while the block is on the canonical chain.
+    undo: Option<BlockUndo>,
+}
+
+///
+/// Block tree keyed by header hash holding the competing branches of the chain
+///
+/// The canonical branch is chosen by the [`ForkChoice`] rule and its blocks
+/// are executed on the [`WorldState`]. The blocks of the other branches are
+/// only checked against their header, they are executed when their branch
+/// becomes canonical.
+#[derive(Debug)]
+pub struct BlockTree<F> {
+    nodes: HashMap<BlockHash, Node>,
+    /// Hash of the canonical block at each height.
+    canonical: Vec<BlockHash>,
+    invalid: HashSet<BlockHash>,
+    state: WorldState,
+    fork_choice: F,
+    reorgs: broadcast::Sender<Reorg>,
+}
+
+impl<F: ForkChoice> BlockTree<F> {
+    /// The tree holding only `genesis`, with `state` the world state after it
+    pub fn new(genesis: Block, state: WorldState, fork_choice: F) -> Self {
+        let hash = genesis.header.hash();
+        let node = Node {
+            total_weight: fork_choice.weight(&genesis.header),
+            block: genesis,
+            undo: None,
+        };
+        BlockTree {
+            nodes: HashMap::from([(hash, node)]),
+            canonical: vec![hash],
+            invalid: HashSet::new(),
+            state,
+            fork_choice,
+            reorgs: broadcast::channel(64).0,
+        }
+    }
+
+    /// Receive the reorgs happening from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Reorg> {
+        self.reorgs.subscribe()
+    }
+
+    /// The last block of the canonical chain
+    pub fn tip(&self) -> &Block {
+        &self.nodes[self.canonical.last().unwrap()].block
+    }
+
+    pub fn state(&self) -> &WorldState {
+        &self.state
+    }
+
+    pub fn get(&self, hash: &BlockHash) -> Option<&Block> {
+        self.nodes.get(hash).map(|node| &node.block)
+    }
+
+    /// Hash of the canonical block at `block_height`
+    pub fn canonical_hash(&self, block_height: u32) -> Option<BlockHash> {
+        self.canonical.get(block_height as usize).copied()
+    }
+
+    /// The number of blocks of every branch
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The canonical chain, from the genesis block to the tip
+    pub fn canonical_chain(&self) -> BlockList {
+        let mut list = BlockList::new();
+        for hash in &self.canonical {
+            list.insert_at_tail(self.nodes[hash].block.clone());
+        }
+        list
+    }
+
+    fn is_canonical(&self, hash: &BlockHash, block_height: u32) -> bool {
+        self.canonical_hash(block_height).as_ref() == Some(hash)
+    }
+
+    /// Store the block and switch to its branch when the fork choice prefers it
+    pub fn insert(&mut self, block: Block) -> Result<InsertOutcome, ChainError> {
+        let hash = block.header.hash();
+        if self.nodes.contains_key(&hash) {
+            return Ok(InsertOutcome::AlreadyKnown);
+        }
+        let parent_hash = block.header.parent_hash;
+        if self.invalid.contains(&parent_hash) {
+            self.invalid.insert(hash);
+            return Err(ChainError::InvalidAncestor(parent_hash));
+        }
+        let parent = self
+            .nodes
+            .get(&parent_hash)
+            .ok_or(ChainError::UnknownParent(parent_hash))?;
+        let height = block.header.block_height;
+        if parent.block.header.block_height.checked_add(1) != Some(height) || !block.header.verify()
+        {
+            return Err(StateTransitionError::invalid_header(height).into());
+        }
+        if merkle_root(&block.transactions) != block.header.transactions_root {
+            return Err(StateTransitionError::transactions_root_mismatch(height).into());
+        }
+
+        let total_weight = parent
+            .total_weight
+            .saturating_add(self.fork_choice.weight(&block.header));
+        let tip_weight = self.nodes[self.canonical.last().unwrap()].total_weight;
+        self.nodes.insert(
+            hash,
+            Node {
+                block,
+                total_weight,
+                undo: None,
+            },
+        );
+        if total_weight <= tip_weight {
+            return Ok(InsertOutcome::SideBranch);
+        }
+        self.switch_to(hash)
+    }
+
+    /// Make the branch ending at `tip` canonical, detaching the blocks above
+    /// the fork point and executing the blocks of the branch
+    fn switch_to(&mut self, tip: BlockHash) -> Result<InsertOutcome, ChainError> {
+        let mut branch = vec![];
+        let mut cursor = tip;
+        loop {
+            let node = self.nodes.get(&cursor);
+            let header = &node.ok_or(ChainError::UnknownParent(cursor))?.block.header;
+            if self.is_canonical(&cursor, header.block_height) {
+                break;
+            }
+            branch.push(cursor);
+            cursor = header.parent_hash;
+        }
+        branch.reverse();
+        let fork_point = cursor;
+        let fork_height = self.nodes[&fork_point].block.header.block_height as usize;
+
+        let detached = self.canonical.split_off(fork_height + 1);
+        for hash in detached.iter().rev() {
+            self.detach(hash);
+        }
+        for (index, hash) in branch.iter().enumerate() {
+            if let Err(e) = self.attach(hash) {
+                // Restore the previous canonical chain and forget the failed
+                // block with every block descending from it
+                for hash in self.canonical.split_off(fork_height + 1).iter().rev() {
+                    self.detach(hash);
+                }
+                self.reject_subtree(branch[index]);
+                for hash in &detached {
+                    self.attach(hash)
+                        .expect("detached blocks were valid on top of the fork point");
+                }
+                return Err(e.into());
+            }
+        }
+
+        if detached.is_empty() {
+            return Ok(InsertOutcome::Extended);
+        }
+        let reorg = Reorg {
+            fork_point,
+            detached: detached
+                .iter()
+                .rev()
+                .map(|hash| self.nodes[hash].block.clone())
+                .collect(),
+            attached: branch
+                .iter()
+                .map(|hash| self.nodes[hash].block.clone())
+                .collect(),
+        };
+        // Nobody listening is not an error
+        let _ = self.reorgs.send(reorg.clone());
+        Ok(InsertOutcome::Reorged(reorg))
+    }
+
+    /// Forget `root` and its descendants on every branch, rejecting the blocks built on them
+    fn reject_subtree(&mut self, root: BlockHash) {
+        let mut children: HashMap<BlockHash, Vec<BlockHash>> = HashMap::new();
+        for (hash, node) in &self.nodes {
+            let parent_hash = node.block.header.parent_hash;
+            children.entry(parent_hash).or_default().push(*hash);
+        }
+        let mut rejected = vec![root];
+        while let Some(hash) = rejected.pop() {
+            self.nodes.remove(&hash);
+            self.invalid.insert(hash);
+            rejected.extend(children.remove(&hash).unwrap_or_default());
+        }
+    }
+
+    fn attach(&mut self, hash: &BlockHash) -> Result<(), StateTransitionError> {
+        let node = self.nodes.get_mut(hash).unwrap();
+        node.undo = Some(self.state.apply_block(&node.block)?);
+        self.canonical.push(*hash);
+        Ok(())
+    }
+
+    /// Revert a block that was already removed from `canonical`
+    fn detach(&mut self, hash: &BlockHash) {
+        let undo = self.nodes.get_mut(hash).unwrap().undo.take().unwrap();
+        self.state.undo_block(undo);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::signing_key;
+
+    fn alice() -> Account {
+        signing_key(0, 1).verifying_key().to_bytes().into()
+    }
+
+    fn bob() -> Account {
+        signing_key(0, 2).verifying_key().to_bytes().into()
+    }
+
+    fn genesis() -> Block {
+        Block::default()
+    }
+
+    fn state() -> WorldState {
+        [(alice(), 100)].into_iter().collect()
+    }
+
+    /// A child of `parent` paying `amount` from Alice to Bob
+    fn child(parent: &Block, difficulty: u32, amount: u64, nonce: u64) -> Block {
+        let fields = TransactionFields {
+            recipient: bob(),
+            amount,
+            nonce,
+            ..Default::default()
+        };
+        let transactions = vec![Transaction::sign(fields, &signing_key(0, 1))];
+        let mut header = BlockHeader {
+            block_height: parent.header.block_height + 1,
+            parent_hash: parent.header.hash(),
+            transactions_root: merkle_root(&transactions),
+            consensus_fields: ConsensusFields {
+                difficulty,
+                nonce: 0,
+            },
+        };
+        header.mine();
+        Block {
+            header,
+            transactions,
+        }
+    }
+
+    fn heights(list: &BlockList) -> Vec<u32> {
+        list.iter().map(|block| block.header.block_height).collect()
+    }
+
+    #[test]
+    fn longest_branch_wins_and_reorgs_rewind_the_state() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone(), state(), LongestChain);
+        let mut reorgs = tree.subscribe();
+
+        let a1 = child(&genesis, 0, 10, 0);
+        let a2 = child(&a1, 0, 10, 1);
+        assert_eq!(tree.insert(a1.clone()), Ok(InsertOutcome::Extended));
+        assert_eq!(tree.insert(a2.clone()), Ok(InsertOutcome::Extended));
+        assert_eq!(tree.state().balance(&bob()), 20);
+
+        let b1 = child(&genesis, 0, 1, 0);
+        let b2 = child(&b1, 0, 2, 1);
+        let b3 = child(&b2, 0, 3, 2);
+        assert_eq!(tree.insert(b1.clone()), Ok(InsertOutcome::SideBranch));
+        assert_eq!(tree.insert(b2.clone()), Ok(InsertOutcome::SideBranch));
+        let expected = Reorg {
+            fork_point: genesis.header.hash(),
+            detached: vec![a2, a1],
+            attached: vec![b1, b2, b3.clone()],
+        };
+        assert_eq!(
+            tree.insert(b3.clone()),
+            Ok(InsertOutcome::Reorged(expected.clone()))
+        );
+        assert_eq!(reorgs.try_recv().unwrap(), expected);
+        assert_eq!(tree.insert(b3), Ok(InsertOutcome::AlreadyKnown));
+
+        assert_eq!(tree.state().balance(&bob()), 6);
+        assert_eq!(tree.state().nonce(&alice()), 3);
+        assert_eq!(tree.len(), 6);
+        assert_eq!(heights(&tree.canonical_chain()), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn heaviest_branch_wins_over_a_longer_one() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone(), state(), HeaviestChain);
+        let a1 = child(&genesis, 0, 10, 0);
+        let a2 = child(&a1, 0, 10, 1);
+        tree.insert(a1).unwrap();
+        tree.insert(a2).unwrap();
+
+        let heavy = child(&genesis, 4, 50, 0);
+        assert!(matches!(
+            tree.insert(heavy.clone()),
+            Ok(InsertOutcome::Reorged(_))
+        ));
+        assert_eq!(tree.tip(), &heavy);
+        assert_eq!(tree.state().balance(&bob()), 50);
+    }
+
+    #[test]
+    fn invalid_branch_keeps_the_canonical_chain() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone(), state(), LongestChain);
+        let a1 = child(&genesis, 0, 10, 0);
+        tree.insert(a1.clone()).unwrap();
+        let before = tree.state().clone();
+
+        let b1 = child(&genesis, 0, 60, 0);
+        let b2 = child(&b1, 0, 60, 1);
+        let b3 = child(&b2, 0, 1, 2);
+        tree.insert(b1).unwrap();
+        assert_eq!(
+            tree.insert(b2.clone()),
+            Err(ChainError::StateTransition(
+                StateTransitionError::transaction(
+                    2,
+                    0,
+                    b2.transactions[0].tx_id,
+                    StateTransitionReason::InsufficientBalance
+                )
+            ))
+        );
+        assert_eq!(
+            tree.insert(b3),
+            Err(ChainError::InvalidAncestor(b2.header.hash()))
+        );
+        assert_eq!(tree.tip(), &a1);
+        assert_eq!(tree.state(), &before);
+    }
+
+    #[test]
+    fn rejects_every_descendant_of_an_invalid_block() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone(), state(), LongestChain);
+        let a1 = child(&genesis, 0, 10, 0);
+        let a2 = child(&a1, 0, 10, 1);
+        let a3 = child(&a2, 0, 10, 2);
+        for block in [a1, a2, a3.clone()] {
+            tree.insert(block).unwrap();
+        }
+
+        // b2 cannot execute, it has two children on side branches
+        let b1 = child(&genesis, 0, 1, 0);
+        let b2 = child(&b1, 0, 200, 1);
+        let b3a = child(&b2, 0, 1, 2);
+        let b3b = child(&b2, 0, 2, 2);
+        for block in [b1, b2, b3a.clone(), b3b.clone()] {
+            assert_eq!(tree.insert(block), Ok(InsertOutcome::SideBranch));
+        }
+        let b4b = child(&b3b, 0, 1, 3);
+        assert!(matches!(
+            tree.insert(b4b),
+            Err(ChainError::StateTransition(_))
+        ));
+        let b4a = child(&b3a, 0, 1, 3);
+        assert_eq!(
+            tree.insert(b4a),
+            Err(ChainError::InvalidAncestor(b3a.header.hash()))
+        );
+        assert_eq!(tree.tip(), &a3);
+        assert_eq!(tree.len(), 5);
+    }
+
+    #[test]
+    fn rejects_blocks_that_do_not_connect() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone(), state(), LongestChain);
+        let a1 = child(&genesis, 0, 10, 0);
+        let orphan = child(&a1, 0, 10, 1);
+        assert_eq!(
+            tree.insert(orphan),
+            Err(ChainError::UnknownParent(a1.header.hash()))
+        );
+
+        let mut skipped = child(&genesis, 0, 10, 0);
+        skipped.header.block_height = 2;
+        assert_eq!(
+            tree.insert(skipped),
+            Err(ChainError::StateTransition(
+                StateTransitionError::invalid_header(2)
+            ))
+        );
+        assert_eq!(tree.len(), 1);
+    }
+}
@@ -0,0 +1,491 @@
+use crate::server::*;
+use async_trait::async_trait;
+use core::ops::Range;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The delay added before each call reaches the wrapped server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    None,
+    Fixed(Duration),
+    /// Uniformly distributed between `min` and `max`.
+    Uniform {
+        min: Duration,
+        max: Duration,
+    },
+    /// `base`, plus `spike` for a `probability` fraction of the calls.
+    Spiky {
+        base: Duration,
+        spike: Duration,
+        probability: f64,
+    },
+}
+
+/// The faults injected by [`ChaosServerApi`].
+///
+/// Every `*_rate` is the probability, between `0.0` and `1.0`, that a call
+/// is affected by the fault. [`ChaosServerApi::new`] clamps the rates and
+/// the probability of [`Latency::Spiky`] to this interval, NaN counting as
+/// `0.0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    /// Seed of the random generator deciding the faults.
+    pub seed: u64,
+    pub latency: Latency,
+    /// Fail the call with [`ServerError::Unavailable`].
+    pub error_rate: f64,
+    /// Drop a random number of items at the end of the result.
+    pub truncate_rate: f64,
+    /// Shuffle the items of the result.
+    pub reorder_rate: f64,
+    /// Flip a byte of the `tx_id` of one transaction of the result.
+    pub corrupt_rate: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            seed: 0,
+            latency: Latency::None,
+            error_rate: 0.0,
+            truncate_rate: 0.0,
+            reorder_rate: 0.0,
+            corrupt_rate: 0.0,
+        }
+    }
+}
+
+/// `rate` as a probability `gen_bool` accepts
+fn probability(rate: f64) -> f64 {
+    if rate.is_nan() {
+        0.0
+    } else {
+        rate.clamp(0.0, 1.0)
+    }
+}
+
+/// The faults drawn for one call, before the wrapped server is called.
+struct Faults {
+    delay: Duration,
+    error: bool,
+    truncate: Option<f64>,
+    reorder: Option<u64>,
+    corrupt: Option<(f64, f64, usize)>,
+}
+
+///
+/// `ServerAPI` decorator injecting faults for resilience testing
+///
+/// The faults of a call are drawn from a generator seeded with
+/// [`ChaosConfig::seed`], so the same sequence of calls always gets the same
+/// faults.
+#[derive(Debug)]
+pub struct ChaosServerApi<S> {
+    inner: S,
+    config: ChaosConfig,
+    rng: Mutex<ChaCha8Rng>,
+}
+
+impl<S: ServerAPI> ChaosServerApi<S> {
+    pub fn new(inner: S, mut config: ChaosConfig) -> Self {
+        for rate in [
+            &mut config.error_rate,
+            &mut config.truncate_rate,
+            &mut config.reorder_rate,
+            &mut config.corrupt_rate,
+        ] {
+            *rate = probability(*rate);
+        }
+        if let Latency::Spiky { probability: p, .. } = &mut config.latency {
+            *p = probability(*p);
+        }
+        let rng = Mutex::new(ChaCha8Rng::seed_from_u64(config.seed));
+        ChaosServerApi { inner, config, rng }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn draw_faults(&self) -> Faults {
+        let mut rng = self.rng.lock().unwrap();
+        let delay = match self.config.latency {
+            Latency::None => Duration::ZERO,
+            Latency::Fixed(delay) => delay,
+            Latency::Uniform { min, max } if min < max => rng.gen_range(min..=max),
+            Latency::Uniform { min, .. } => min,
+            Latency::Spiky {
+                base,
+                spike,
+                probability,
+            } => {
+                if rng.gen_bool(probability) {
+                    base + spike
+                } else {
+                    base
+                }
+            }
+        };
+        Faults {
+            delay,
+            error: rng.gen_bool(self.config.error_rate),
+            truncate: rng.gen_bool(self.config.truncate_rate).then(|| rng.gen()),
+            reorder: rng.gen_bool(self.config.reorder_rate).then(|| rng.gen()),
+            corrupt: rng
+                .gen_bool(self.config.corrupt_rate)
+                .then(|| (rng.gen(), rng.gen(), rng.gen_range(0..32))),
+        }
+    }
+
+    /// Wait for the drawn latency, then fail the call
+    async fn delay<T>(
+        &self,
+        result: impl std::future::Future<Output = Result<T, ServerError>>,
+    ) -> Result<T, ServerError> {
+        Self::disrupt(&self.draw_faults()).await?;
+        result.await
+    }
+
+    async fn disrupt(faults: &Faults) -> Result<(), ServerError> {
+        if !faults.delay.is_zero() {
+            tokio::time::sleep(faults.delay).await;
+        }
+        if faults.error {
+            return Err(ServerError::Unavailable);
+        }
+        Ok(())
+    }
+
+    /// Wait for the drawn latency, then fail the call or alter its result
+    async fn apply<T>(
+        faults: &Faults,
+        result: impl std::future::Future<Output = Result<Vec<T>, ServerError>>,
+    ) -> Result<Vec<T>, ServerError> {
+        Self::disrupt(faults).await?;
+        let mut items = result.await?;
+        if let Some(kept) = faults.truncate {
+            // Keep strictly fewer items than returned
+            let len = (items.len() as f64 * kept) as usize;
+            items.truncate(len.min(items.len().saturating_sub(1)));
+        }
+        if let Some(seed) = faults.reorder {
+            items.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+        }
+        Ok(items)
+    }
+}
+
+#[async_trait]
+impl<S: ServerAPI + Send + Sync> ServerAPI for ChaosServerApi<S> {
+    async fn block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<BlockHeader>, ServerError> {
+        let faults = self.draw_faults();
+        Self::apply(&faults, self.inner.block_headers(block_height_range)).await
+    }
+
+    async fn block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<Vec<Transaction>>, ServerError> {
+        let faults = self.draw_faults();
+        let mut transactions =
+            Self::apply(&faults, self.inner.block_transactions(block_height_range)).await?;
+        if let Some((block, transaction, byte)) = faults.corrupt {
+            let mut blocks: Vec<_> = transactions
+                .iter_mut()
+                .filter(|txs| !txs.is_empty())
+                .collect();
+            if !blocks.is_empty() {
+                let index = (blocks.len() as f64 * block) as usize;
+                let txs = &mut blocks[index];
+                let index = (txs.len() as f64 * transaction) as usize;
+                txs[index].tx_id.0[byte] ^= 0xff;
+            }
+        }
+        Ok(transactions)
+    }
+
+    // Single answers are only delayed or failed
+    async fn latest_height(&self) -> Result<u32, ServerError> {
+        self.delay(self.inner.latest_height()).await
+    }
+
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+        self.delay(self.inner.header_by_hash(hash)).await
+    }
+
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+        self.delay(self.inner.block_by_hash(hash)).await
+    }
+
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError> {
+        self.delay(self.inner.transaction_by_id(tx_id)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::{Blocks, BuildError};
+    use crate::generator::{ChainGenerator, GeneratorConfig};
+    use crate::store::BlockStore;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    const LEN: u32 = 8;
+
+    fn chain() -> Vec<Block> {
+        ChainGenerator::new(GeneratorConfig::default())
+            .take(LEN as usize)
+            .collect()
+    }
+
+    /// The results of the parallel and the backward builders through the faults
+    async fn build_through(config: ChaosConfig) -> [Result<Vec<Block>, BuildError>; 2] {
+        let store: BlockStore = chain().into_iter().collect();
+        let server = Arc::new(ChaosServerApi::new(store, config));
+        let parallel = server.clone().build_blocks_parallel(0..LEN).await;
+        let backward = server.build_blocks_backward(vec![], 0..LEN).await;
+        [parallel, backward]
+    }
+
+    fn store(len: u32) -> BlockStore {
+        (0..len)
+            .map(|height| Block {
+                header: BlockHeader {
+                    block_height: height,
+                    ..Default::default()
+                },
+                transactions: (0..3u8)
+                    .map(|i| Transaction {
+                        tx_id: TransactionId([i; 32]),
+                        ..Default::default()
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn heights(headers: &[BlockHeader]) -> Vec<u32> {
+        headers.iter().map(|h| h.block_height).collect()
+    }
+
+    #[tokio::test]
+    async fn same_seed_gives_same_faults() {
+        let config = ChaosConfig {
+            seed: 7,
+            error_rate: 0.3,
+            truncate_rate: 0.3,
+            reorder_rate: 0.3,
+            ..Default::default()
+        };
+        let first = ChaosServerApi::new(store(50), config.clone());
+        let second = ChaosServerApi::new(store(50), config);
+        for start in 0..40 {
+            assert_eq!(
+                first.block_headers(start..start + 10).await,
+                second.block_headers(start..start + 10).await
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn injects_each_fault() {
+        let failing = ChaosServerApi::new(
+            store(10),
+            ChaosConfig {
+                error_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            failing.block_headers(0..10).await,
+            Err(ServerError::Unavailable)
+        );
+
+        let truncating = ChaosServerApi::new(
+            store(10),
+            ChaosConfig {
+                truncate_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        let headers = truncating.block_headers(0..10).await.unwrap();
+        assert!(headers.len() < 10);
+        assert_eq!(
+            heights(&headers),
+            (0..headers.len() as u32).collect::<Vec<_>>()
+        );
+
+        let reordering = ChaosServerApi::new(
+            store(10),
+            ChaosConfig {
+                reorder_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        let mut reordered = heights(&reordering.block_headers(0..10).await.unwrap());
+        assert_ne!(reordered, (0..10).collect::<Vec<_>>());
+        reordered.sort();
+        assert_eq!(reordered, (0..10).collect::<Vec<_>>());
+
+        let corrupting = ChaosServerApi::new(
+            store(10),
+            ChaosConfig {
+                corrupt_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        let expected = store(10).block_transactions(0..10).await.unwrap();
+        let corrupted = corrupting.block_transactions(0..10).await.unwrap();
+        let changed = expected
+            .iter()
+            .flatten()
+            .zip(corrupted.iter().flatten())
+            .filter(|(expected, corrupted)| expected != corrupted)
+            .count();
+        assert_eq!(changed, 1);
+    }
+
+    #[tokio::test]
+    async fn delays_calls() {
+        let slow = ChaosServerApi::new(
+            store(1),
+            ChaosConfig {
+                latency: Latency::Uniform {
+                    min: Duration::from_millis(20),
+                    max: Duration::from_millis(30),
+                },
+                ..Default::default()
+            },
+        );
+        let started = Instant::now();
+        slow.block_headers(0..1).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn builders_go_through_latency() {
+        let config = ChaosConfig {
+            latency: Latency::Spiky {
+                base: Duration::from_millis(1),
+                spike: Duration::from_millis(5),
+                probability: 0.3,
+            },
+            ..Default::default()
+        };
+        for built in build_through(config).await {
+            assert_eq!(built.unwrap(), chain());
+        }
+    }
+
+    #[tokio::test]
+    async fn builders_report_unavailable_servers() {
+        let config = ChaosConfig {
+            error_rate: 1.0,
+            ..Default::default()
+        };
+        for built in build_through(config).await {
+            assert!(matches!(
+                built,
+                Err(BuildError::Server(ServerError::Unavailable))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn builders_report_truncated_answers() {
+        let config = ChaosConfig {
+            truncate_rate: 1.0,
+            ..Default::default()
+        };
+        for built in build_through(config).await {
+            assert!(matches!(
+                built,
+                Err(BuildError::Server(ServerError::MissingHeight(0)))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn builders_reject_reordered_answers() {
+        let config = ChaosConfig {
+            seed: 3,
+            reorder_rate: 1.0,
+            ..Default::default()
+        };
+        for built in build_through(config).await {
+            assert!(matches!(built, Err(BuildError::StateTransition(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn builders_reject_corrupted_transactions() {
+        let config = ChaosConfig {
+            corrupt_rate: 1.0,
+            ..Default::default()
+        };
+        for built in build_through(config).await {
+            assert!(matches!(
+                built,
+                Err(BuildError::StateTransition(StateTransitionError {
+                    reason: StateTransitionReason::TransactionsRootMismatch,
+                    ..
+                }))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn builders_never_return_wrong_blocks() {
+        for seed in 0..8 {
+            let config = ChaosConfig {
+                seed,
+                error_rate: 0.2,
+                truncate_rate: 0.2,
+                reorder_rate: 0.2,
+                corrupt_rate: 0.2,
+                ..Default::default()
+            };
+            for built in build_through(config).await {
+                match built {
+                    Ok(blocks) => assert_eq!(blocks, chain()),
+                    Err(BuildError::Server(_) | BuildError::StateTransition(_)) => {}
+                    Err(e) => panic!("unexpected error {:?}", e),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn clamps_the_rates() {
+        let config = ChaosConfig {
+            error_rate: 2.0,
+            truncate_rate: -1.0,
+            reorder_rate: f64::NAN,
+            latency: Latency::Spiky {
+                base: Duration::ZERO,
+                spike: Duration::from_millis(1),
+                probability: 5.0,
+            },
+            ..Default::default()
+        };
+        let chaos = ChaosServerApi::new(store(10), config);
+        assert_eq!(chaos.config.error_rate, 1.0);
+        assert_eq!(chaos.config.truncate_rate, 0.0);
+        assert_eq!(chaos.config.reorder_rate, 0.0);
+        assert_eq!(
+            chaos.block_headers(0..10).await,
+            Err(ServerError::Unavailable)
+        );
+    }
+}
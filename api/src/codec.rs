@@ -0,0 +1,384 @@
+use crate::server::*;
+use std::fmt;
+
+/// Version of the encoding, the first byte of [`encode`]'s output.
+pub const VERSION: u8 = 1;
+
+/// The error returned when the bytes are not a valid encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes were encoded with a version this code does not know.
+    UnsupportedVersion(u8),
+    /// The input ends before the value does.
+    UnexpectedEnd { needed: usize, remaining: usize },
+    /// The input goes on after the value.
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "Decode error: unsupported version {}", version)
+            }
+            DecodeError::UnexpectedEnd { needed, remaining } => write!(
+                f,
+                "Decode error: {} bytes needed, {} remaining",
+                needed, remaining
+            ),
+            DecodeError::TrailingBytes(len) => {
+                write!(f, "Decode error: {} trailing bytes", len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// The bytes being decoded.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Fail unless `len` more bytes can be read
+    pub fn ensure(&self, len: usize) -> Result<(), DecodeError> {
+        if len > self.bytes.len() {
+            return Err(DecodeError::UnexpectedEnd {
+                needed: len,
+                remaining: self.bytes.len(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        self.ensure(len)?;
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
+
+/// Types with a canonical binary encoding.
+///
+/// Integers are little endian, arrays are written as is, and a `Vec` is
+/// its length as a `u32` followed by its items.
+pub trait Encode {
+    fn encode_to(&self, out: &mut Vec<u8>);
+}
+
+/// Types that can be read back from their [`Encode`] encoding.
+pub trait Decode: Sized {
+    /// Smallest number of bytes of an encoded value, to reject lengths
+    /// that cannot fit in the input before allocating for them.
+    const MIN_LEN: usize;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError>;
+}
+
+/// The encoding of the value without the version, the bytes that are hashed
+pub fn canonical_bytes<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = vec![];
+    value.encode_to(&mut out);
+    out
+}
+
+/// The version byte followed by the encoding of the value
+pub fn encode<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = vec![VERSION];
+    value.encode_to(&mut out);
+    out
+}
+
+/// Decode a value written by [`encode`], which must use all of `bytes`
+pub fn decode<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let mut reader = Reader::new(bytes);
+    let [version] = reader.array()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let value = T::decode_from(&mut reader)?;
+    match reader.remaining() {
+        0 => Ok(value),
+        len => Err(DecodeError::TrailingBytes(len)),
+    }
+}
+
+impl Encode for u32 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u32 {
+    const MIN_LEN: usize = 4;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(u32::from_le_bytes(reader.array()?))
+    }
+}
+
+impl Encode for u64 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u64 {
+    const MIN_LEN: usize = 8;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(u64::from_le_bytes(reader.array()?))
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    const MIN_LEN: usize = N;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        reader.array()
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let len = u32::try_from(self.len()).expect("at most u32::MAX items are encoded");
+        len.encode_to(out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_to(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    const MIN_LEN: usize = 4;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let len = u32::decode_from(reader)? as usize;
+        reader.ensure(len.saturating_mul(T::MIN_LEN))?;
+        (0..len).map(|_| T::decode_from(reader)).collect()
+    }
+}
+
+impl Encode for TransactionFields {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.sender.encode_to(out);
+        self.recipient.encode_to(out);
+        self.amount.encode_to(out);
+        self.fee.encode_to(out);
+        self.nonce.encode_to(out);
+    }
+}
+
+impl Decode for TransactionFields {
+    const MIN_LEN: usize = 88;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(TransactionFields {
+            sender: Decode::decode_from(reader)?,
+            recipient: Decode::decode_from(reader)?,
+            amount: Decode::decode_from(reader)?,
+            fee: Decode::decode_from(reader)?,
+            nonce: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.tx_id.encode_to(out);
+        self.transaction_fields.encode_to(out);
+        self.signature.encode_to(out);
+    }
+}
+
+impl Decode for Transaction {
+    const MIN_LEN: usize = 32 + TransactionFields::MIN_LEN + Signature::MIN_LEN;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Transaction {
+            tx_id: Decode::decode_from(reader)?,
+            transaction_fields: Decode::decode_from(reader)?,
+            signature: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for ConsensusFields {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.difficulty.encode_to(out);
+        self.nonce.encode_to(out);
+    }
+}
+
+impl Decode for ConsensusFields {
+    const MIN_LEN: usize = 12;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(ConsensusFields {
+            difficulty: Decode::decode_from(reader)?,
+            nonce: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.block_height.encode_to(out);
+        self.parent_hash.encode_to(out);
+        self.transactions_root.encode_to(out);
+        self.consensus_fields.encode_to(out);
+    }
+}
+
+impl Decode for BlockHeader {
+    const MIN_LEN: usize = 4 + 32 + 32 + ConsensusFields::MIN_LEN;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(BlockHeader {
+            block_height: Decode::decode_from(reader)?,
+            parent_hash: Decode::decode_from(reader)?,
+            transactions_root: Decode::decode_from(reader)?,
+            consensus_fields: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.transactions.encode_to(out);
+    }
+}
+
+impl Decode for Block {
+    const MIN_LEN: usize = BlockHeader::MIN_LEN + 4;
+
+    fn decode_from(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Block {
+            header: Decode::decode_from(reader)?,
+            transactions: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{ChainGenerator, GeneratorConfig, TxCount};
+    use crate::merkle::Hash;
+
+    fn block() -> Block {
+        let config = GeneratorConfig {
+            tx_count: TxCount::Fixed(3),
+            ..Default::default()
+        };
+        ChainGenerator::new(config).nth(2).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let block = block();
+        let bytes = encode(&block);
+        assert_eq!(bytes.len(), 1 + 80 + 4 + 3 * 184);
+        assert_eq!(decode::<Block>(&bytes), Ok(block.clone()));
+        assert_eq!(encode(&block), bytes);
+        assert_eq!(
+            decode::<BlockHeader>(&encode(&block.header)),
+            Ok(block.header)
+        );
+        assert_eq!(
+            decode::<Vec<Transaction>>(&encode(&block.transactions)),
+            Ok(block.transactions)
+        );
+    }
+
+    #[test]
+    fn header_layout_is_stable() {
+        let header = BlockHeader {
+            block_height: 0x0102,
+            parent_hash: Hash([0xaa; 32]),
+            transactions_root: Hash([0xbb; 32]),
+            consensus_fields: ConsensusFields {
+                difficulty: 3,
+                nonce: 0x0a0b,
+            },
+        };
+        let mut expected = vec![0x02, 0x01, 0, 0];
+        expected.extend([0xaa; 32]);
+        expected.extend([0xbb; 32]);
+        expected.extend([3, 0, 0, 0]);
+        expected.extend([0x0b, 0x0a, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(canonical_bytes(&header), expected);
+        assert_eq!(encode(&header)[0], VERSION);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let bytes = encode(&block());
+        for len in 0..bytes.len() {
+            assert!(
+                matches!(
+                    decode::<Block>(&bytes[..len]),
+                    Err(DecodeError::UnexpectedEnd { .. })
+                ),
+                "truncated to {}",
+                len
+            );
+        }
+
+        let mut other_version = bytes.clone();
+        other_version[0] = VERSION + 1;
+        assert_eq!(
+            decode::<Block>(&other_version),
+            Err(DecodeError::UnsupportedVersion(VERSION + 1))
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            decode::<Block>(&trailing),
+            Err(DecodeError::TrailingBytes(1))
+        );
+    }
+
+    #[test]
+    fn rejects_lengths_larger_than_the_input() {
+        let mut bytes = encode(&Block::default());
+        // The transaction count, right after the version and the header
+        bytes[81..85].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            decode::<Block>(&bytes),
+            Err(DecodeError::UnexpectedEnd {
+                needed: u32::MAX as usize * 184,
+                remaining: 0
+            })
+        );
+    }
+}
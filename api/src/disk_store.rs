@@ -0,0 +1,782 @@
+use crate::blocks::{BlockSink, BuildError};
+use crate::codec;
+use crate::merkle::Hash;
+use crate::server::*;
+use async_trait::async_trait;
+use core::ops::Range;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const FORMAT_FILE: &str = "format";
+const LOG_FILE: &str = "blocks.log";
+const INDEX_FILE: &str = "blocks.idx";
+const TX_INDEX_FILE: &str = "transactions.idx";
+
+/// `magic | version`, the version little endian.
+///
+/// The version is bumped whenever the layout of the other files changes.
+const FORMAT_MAGIC: &[u8; 8] = b"blkstore";
+const FORMAT_VERSION: u32 = 2;
+/// `payload length | block height | crc32 of the payload`, all little endian.
+///
+/// The payload is the block in the binary encoding of [`crate::codec`].
+const RECORD_HEADER_LEN: u64 = 12;
+/// `block height | record offset in the log | block hash`, integers little endian.
+const INDEX_ENTRY_LEN: usize = 44;
+/// `tx_id | record offset in the log | index in the block`, integers little endian.
+const TX_INDEX_ENTRY_LEN: usize = 44;
+
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    offset: u64,
+    len: u32,
+    hash: BlockHash,
+}
+
+#[derive(Debug)]
+struct Inner {
+    log: File,
+    index_file: File,
+    tx_index_file: File,
+    index: BTreeMap<u32, Location>,
+    /// Height of each block by its hash.
+    by_hash: HashMap<BlockHash, u32>,
+    /// Height of each stored record by its offset, to tell the entries of
+    /// replaced blocks apart.
+    heights: HashMap<u64, u32>,
+    /// Record offset and index in the block of each transaction, in the
+    /// last appended block containing it.
+    by_tx: HashMap<TransactionId, (u64, u32)>,
+    /// End of the last complete record of the log.
+    end: u64,
+}
+
+impl Inner {
+    fn insert(&mut self, height: u32, location: Location) {
+        if let Some(replaced) = self.index.insert(height, location) {
+            self.heights.remove(&replaced.offset);
+            self.by_hash.remove(&replaced.hash);
+        }
+        self.heights.insert(location.offset, height);
+        self.by_hash.insert(location.hash, height);
+    }
+
+    /// Index the transactions of the block stored at `offset`
+    fn insert_transactions(&mut self, offset: u64, block: &Block) -> io::Result<()> {
+        self.tx_index_file
+            .write_all(&tx_index_entries(offset, block))?;
+        self.insert_tx_ids(offset, block);
+        Ok(())
+    }
+
+    fn insert_tx_ids(&mut self, offset: u64, block: &Block) {
+        for (tx_index, tx) in (0..).zip(&block.transactions) {
+            self.by_tx.insert(tx.tx_id, (offset, tx_index));
+        }
+    }
+
+    /// Write the record at the end of the log and its index entries
+    fn write_block(
+        &mut self,
+        record: &[u8],
+        height: u32,
+        location: Location,
+        block: &Block,
+    ) -> io::Result<()> {
+        self.log.seek(SeekFrom::Start(location.offset))?;
+        self.log.write_all(record)?;
+        self.log.sync_data()?;
+        self.index_file.write_all(&index_entry(height, location))?;
+        self.tx_index_file
+            .write_all(&tx_index_entries(location.offset, block))
+    }
+}
+
+///
+/// Blocks persisted in an append-only log with a height index
+///
+/// Each block is appended to `blocks.log` as one checksummed record, and its
+/// offset is then appended to `blocks.idx`. A block only becomes visible
+/// once its record is synced to disk, so a crash while appending leaves at
+/// most a torn record at the end of the log, which is cut off when the store
+/// is reopened. Reopening only reads the indexes and scans the records
+/// written after their last entries. Appending a block at an already stored
+/// height replaces it.
+///
+/// The block hashes are kept in the height index, and the transaction ids in
+/// `transactions.idx`, so lookups by hash and by id do not read the log.
+///
+/// The layout version is written to `format` when the store is created, and
+/// a store of another version, or written before the versioning, is refused.
+///
+/// The inherent methods block on the file system; the `ServerAPI` methods
+/// run them with `tokio::task::spawn_blocking` so they never stall the
+/// runtime.
+#[derive(Debug, Clone)]
+pub struct DiskBlockStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl DiskBlockStore {
+    /// Open the store in `dir`, creating it if needed and recovering from an interrupted append
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        check_format(dir)?;
+        let mut log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(LOG_FILE))?;
+        let mut index_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(INDEX_FILE))?;
+        let tx_index_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(TX_INDEX_FILE))?;
+        let log_len = log.metadata()?.len();
+
+        // Keep the index entries that point to a record of the log
+        let mut entries = vec![];
+        index_file.read_to_end(&mut entries)?;
+        let mut index = vec![];
+        let mut indexed = 0;
+        let mut end = 0;
+        for entry in entries.chunks_exact(INDEX_ENTRY_LEN) {
+            let height = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+            let offset = u64::from_le_bytes(entry[4..12].try_into().unwrap());
+            let hash = Hash(entry[12..44].try_into().unwrap());
+            if offset != end || offset + RECORD_HEADER_LEN > log_len {
+                break;
+            }
+            let (len, record_height, _) = read_record_header(&mut log, offset)?;
+            let record_end = offset + RECORD_HEADER_LEN + len as u64;
+            if record_height != height || record_end > log_len {
+                break;
+            }
+            index.push((height, Location { offset, len, hash }));
+            indexed += INDEX_ENTRY_LEN as u64;
+            end = record_end;
+        }
+        index_file.set_len(indexed)?;
+        index_file.seek(SeekFrom::End(0))?;
+
+        // Index the records appended after the last index entry, and cut off
+        // the log at the first torn or corrupted record
+        while end + RECORD_HEADER_LEN <= log_len {
+            let (len, height, crc) = read_record_header(&mut log, end)?;
+            let record_end = end + RECORD_HEADER_LEN + len as u64;
+            if record_end > log_len {
+                break;
+            }
+            let mut payload = vec![0; len as usize];
+            log.read_exact(&mut payload)?;
+            if crc32fast::hash(&payload) != crc {
+                break;
+            }
+            let Ok(block) = codec::decode::<Block>(&payload) else {
+                break;
+            };
+            let location = Location {
+                offset: end,
+                len,
+                hash: block.header.hash(),
+            };
+            index_file.write_all(&index_entry(height, location))?;
+            index.push((height, location));
+            end = record_end;
+        }
+        log.set_len(end)?;
+
+        let mut inner = Inner {
+            log,
+            index_file,
+            tx_index_file,
+            index: BTreeMap::new(),
+            by_hash: HashMap::new(),
+            heights: HashMap::new(),
+            by_tx: HashMap::new(),
+            end,
+        };
+        for (height, location) in index {
+            inner.insert(height, location);
+        }
+
+        // The transaction entries follow the order of the log, keep the
+        // entries of the blocks before the last one indexed, and index again
+        // the transactions of the blocks from there
+        let mut entries = vec![];
+        inner.tx_index_file.read_to_end(&mut entries)?;
+        let mut resume = 0;
+        for entry in entries.chunks_exact(TX_INDEX_ENTRY_LEN) {
+            let offset = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+            if offset < resume || offset >= end {
+                break;
+            }
+            resume = offset;
+        }
+        let mut kept = 0;
+        for entry in entries.chunks_exact(TX_INDEX_ENTRY_LEN) {
+            let offset = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+            if offset >= resume {
+                break;
+            }
+            let tx_id = TransactionId(entry[0..32].try_into().unwrap());
+            let tx_index = u32::from_le_bytes(entry[40..44].try_into().unwrap());
+            inner.by_tx.insert(tx_id, (offset, tx_index));
+            kept += TX_INDEX_ENTRY_LEN as u64;
+        }
+        inner.tx_index_file.set_len(kept)?;
+        inner.tx_index_file.seek(SeekFrom::End(0))?;
+        let mut unindexed: Vec<Location> = inner
+            .index
+            .values()
+            .filter(|location| location.offset >= resume)
+            .copied()
+            .collect();
+        unindexed.sort_by_key(|location| location.offset);
+        for location in unindexed {
+            let block = read_block(&mut inner.log, location)?;
+            inner.insert_transactions(location.offset, &block)?;
+        }
+
+        inner.log.sync_all()?;
+        inner.index_file.sync_all()?;
+        inner.tx_index_file.sync_all()?;
+        Ok(DiskBlockStore {
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Durably append the block, it is visible once this returns
+    ///
+    /// On failure, the store is left as it was before the call.
+    pub fn append(&self, block: &Block) -> io::Result<()> {
+        let payload = codec::encode(block);
+        let len = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "block too large"))?;
+        let height = block.header.block_height;
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN as usize + payload.len());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&height.to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        let mut inner = self.inner.lock().unwrap();
+        let offset = inner.end;
+        let indexed = inner.index_file.stream_position()?;
+        let tx_indexed = inner.tx_index_file.stream_position()?;
+        let location = Location {
+            offset,
+            len,
+            hash: block.header.hash(),
+        };
+        if let Err(e) = inner.write_block(&record, height, location, block) {
+            // Drop the partial writes so the next append starts at a record
+            // boundary, and the block is not found after reopening
+            let inner = &mut *inner;
+            let _ = inner.log.set_len(offset);
+            for (file, len) in [
+                (&mut inner.index_file, indexed),
+                (&mut inner.tx_index_file, tx_indexed),
+            ] {
+                let _ = file
+                    .set_len(len)
+                    .and_then(|_| file.seek(SeekFrom::Start(len)));
+            }
+            return Err(e);
+        }
+        inner.end = offset + record.len() as u64;
+        inner.insert(height, location);
+        inner.insert_tx_ids(offset, block);
+        Ok(())
+    }
+
+    pub fn get(&self, height: u32) -> io::Result<Option<Block>> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.index.get(&height).copied() {
+            Some(location) => read_block(&mut inner.log, location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().index.is_empty()
+    }
+
+    /// Run `f` on the blocking threads of the runtime
+    async fn blocking<T, F>(&self, f: F) -> Result<T, ServerError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DiskBlockStore) -> Result<T, ServerError> + Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || f(&store))
+            .await
+            .map_err(|_| ServerError::Unavailable)?
+    }
+
+    /// Collect `f(block)` for every height of the range, see [`crate::store::BlockStore`]
+    fn collect_range<T>(
+        &self,
+        block_height_range: Range<u32>,
+        f: impl Fn(Block) -> T,
+    ) -> Result<Vec<T>, ServerError> {
+        if block_height_range.start > block_height_range.end {
+            return Err(ServerError::InvalidRange {
+                start: block_height_range.start,
+                end: block_height_range.end,
+            });
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let stored = inner.index.len();
+        let mut items = Vec::with_capacity(block_height_range.len().min(stored));
+        for height in block_height_range {
+            let location = inner
+                .index
+                .get(&height)
+                .copied()
+                .ok_or(ServerError::MissingHeight(height))?;
+            let block =
+                read_block(&mut inner.log, location).map_err(|_| ServerError::Unavailable)?;
+            items.push(f(block));
+        }
+        Ok(items)
+    }
+}
+
+/// Check the layout version of the store, writing it if the store is new
+fn check_format(dir: &Path) -> io::Result<()> {
+    let mut expected = FORMAT_MAGIC.to_vec();
+    expected.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    let found = match fs::read(dir.join(FORMAT_FILE)) {
+        Ok(found) => found,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let log_len = match fs::metadata(dir.join(LOG_FILE)) {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e),
+            };
+            if log_len > 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "block store in {} predates format version {FORMAT_VERSION}",
+                        dir.display()
+                    ),
+                ));
+            }
+            let mut file = File::create(dir.join(FORMAT_FILE))?;
+            file.write_all(&expected)?;
+            return file.sync_all();
+        }
+        Err(e) => return Err(e),
+    };
+    if found != expected {
+        let version = found
+            .strip_prefix(FORMAT_MAGIC)
+            .and_then(|version| Some(u32::from_le_bytes(version.try_into().ok()?)));
+        let found = match version {
+            Some(version) => format!("version {version}"),
+            None => "an unknown format".to_string(),
+        };
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "block store in {} has {found}, expected version {FORMAT_VERSION}",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn index_entry(height: u32, location: Location) -> [u8; INDEX_ENTRY_LEN] {
+    let mut entry = [0; INDEX_ENTRY_LEN];
+    entry[0..4].copy_from_slice(&height.to_le_bytes());
+    entry[4..12].copy_from_slice(&location.offset.to_le_bytes());
+    entry[12..44].copy_from_slice(&location.hash.0);
+    entry
+}
+
+fn tx_index_entries(offset: u64, block: &Block) -> Vec<u8> {
+    let mut entries = Vec::with_capacity(block.transactions.len() * TX_INDEX_ENTRY_LEN);
+    for (tx_index, tx) in (0..).zip(&block.transactions) {
+        entries.extend_from_slice(&tx_index_entry(tx.tx_id, offset, tx_index));
+    }
+    entries
+}
+
+fn tx_index_entry(tx_id: TransactionId, offset: u64, tx_index: u32) -> [u8; TX_INDEX_ENTRY_LEN] {
+    let mut entry = [0; TX_INDEX_ENTRY_LEN];
+    entry[0..32].copy_from_slice(&tx_id.0);
+    entry[32..40].copy_from_slice(&offset.to_le_bytes());
+    entry[40..44].copy_from_slice(&tx_index.to_le_bytes());
+    entry
+}
+
+fn read_record_header(log: &mut File, offset: u64) -> io::Result<(u32, u32, u32)> {
+    let mut header = [0; RECORD_HEADER_LEN as usize];
+    log.seek(SeekFrom::Start(offset))?;
+    log.read_exact(&mut header)?;
+    Ok((
+        u32::from_le_bytes(header[0..4].try_into().unwrap()),
+        u32::from_le_bytes(header[4..8].try_into().unwrap()),
+        u32::from_le_bytes(header[8..12].try_into().unwrap()),
+    ))
+}
+
+fn read_block(log: &mut File, location: Location) -> io::Result<Block> {
+    let (_, _, crc) = read_record_header(log, location.offset)?;
+    let mut payload = vec![0; location.len as usize];
+    log.read_exact(&mut payload)?;
+    if crc32fast::hash(&payload) != crc {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "block record checksum mismatch",
+        ));
+    }
+    let block: Block =
+        codec::decode(&payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if block.header.hash() != location.hash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "block hash does not match its index entry",
+        ));
+    }
+    Ok(block)
+}
+
+impl BlockSink for DiskBlockStore {
+    fn store(&mut self, block: Block) -> Result<(), BuildError> {
+        self.append(&block)
+            .map_err(|e| BuildError::Storage(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl ServerAPI for DiskBlockStore {
+    async fn block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<BlockHeader>, ServerError> {
+        self.blocking(|store| store.collect_range(block_height_range, |block| block.header))
+            .await
+    }
+
+    async fn block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<Vec<Transaction>>, ServerError> {
+        self.blocking(|store| store.collect_range(block_height_range, |block| block.transactions))
+            .await
+    }
+
+    async fn latest_height(&self) -> Result<u32, ServerError> {
+        self.blocking(|store| {
+            let inner = store.inner.lock().unwrap();
+            match inner.index.last_key_value() {
+                Some((&height, _)) => Ok(height),
+                None => Err(ServerError::MissingHeight(0)),
+            }
+        })
+        .await
+    }
+
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+        self.block_by_hash(hash).await.map(|block| block.header)
+    }
+
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+        self.blocking(move |store| {
+            let mut inner = store.inner.lock().unwrap();
+            let location = inner
+                .by_hash
+                .get(&hash)
+                .map(|height| inner.index[height])
+                .ok_or(ServerError::UnknownBlock(hash))?;
+            read_block(&mut inner.log, location).map_err(|_| ServerError::Unavailable)
+        })
+        .await
+    }
+
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError> {
+        self.blocking(move |store| {
+            let inner = store.inner.lock().unwrap();
+            inner
+                .by_tx
+                .get(&tx_id)
+                .and_then(|(offset, tx_index)| {
+                    Some(TransactionLocation {
+                        block_height: *inner.heights.get(offset)?,
+                        tx_index: *tx_index as usize,
+                    })
+                })
+                .ok_or(ServerError::UnknownTransaction(tx_id))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(height: u32) -> Block {
+        Block {
+            header: BlockHeader {
+                block_height: height,
+                ..Default::default()
+            },
+            transactions: vec![Transaction::default(); 3],
+        }
+    }
+
+    fn file_len(dir: &Path, name: &str) -> u64 {
+        fs::metadata(dir.join(name)).unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn blocks_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        for height in 0..10 {
+            store.append(&block(height)).unwrap();
+        }
+        drop(store);
+
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 10);
+        assert_eq!(store.get(4).unwrap(), Some(block(4)));
+        assert_eq!(store.get(10).unwrap(), None);
+        let headers = store.block_headers(2..8).await.unwrap();
+        assert_eq!(headers.len(), 6);
+        assert_eq!(
+            store.block_transactions(8..11).await,
+            Err(ServerError::MissingHeight(10))
+        );
+        assert_eq!(
+            store.block_headers(0..u32::MAX).await,
+            Err(ServerError::MissingHeight(10))
+        );
+    }
+
+    #[test]
+    fn recovers_from_a_torn_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        for height in 0..3 {
+            store.append(&block(height)).unwrap();
+        }
+        drop(store);
+
+        // A crash in the middle of writing the fourth record
+        let complete = file_len(dir.path(), LOG_FILE);
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(LOG_FILE))
+            .unwrap();
+        log.write_all(&100u32.to_le_bytes()).unwrap();
+        log.write_all(&3u32.to_le_bytes()).unwrap();
+        log.write_all(b"torn").unwrap();
+        drop(log);
+
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(file_len(dir.path(), LOG_FILE), complete);
+        store.append(&block(3)).unwrap();
+        drop(store);
+
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.get(3).unwrap(), Some(block(3)));
+    }
+
+    #[test]
+    fn rebuilds_a_lost_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        for height in 0..5 {
+            store.append(&block(height)).unwrap();
+        }
+        drop(store);
+
+        // Keep one and a half index entries
+        let index = OpenOptions::new()
+            .write(true)
+            .open(dir.path().join(INDEX_FILE))
+            .unwrap();
+        index.set_len(INDEX_ENTRY_LEN as u64 * 3 / 2).unwrap();
+        drop(index);
+
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.get(4).unwrap(), Some(block(4)));
+        assert_eq!(file_len(dir.path(), INDEX_FILE), 5 * INDEX_ENTRY_LEN as u64);
+    }
+
+    #[tokio::test]
+    async fn looks_up_blocks_and_transactions_after_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let tx = |i: u8| Transaction {
+            tx_id: TransactionId([i; 32]),
+            ..Default::default()
+        };
+        let blocks: Vec<Block> = (0..4u8)
+            .map(|height| Block {
+                header: BlockHeader {
+                    block_height: height as u32,
+                    ..Default::default()
+                },
+                transactions: vec![tx(2 * height), tx(2 * height + 1)],
+            })
+            .collect();
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        for block in &blocks {
+            store.append(block).unwrap();
+        }
+        drop(store);
+
+        // Lose the entries of the last block and half of an entry
+        let tx_index = OpenOptions::new()
+            .write(true)
+            .open(dir.path().join(TX_INDEX_FILE))
+            .unwrap();
+        tx_index
+            .set_len(TX_INDEX_ENTRY_LEN as u64 * 13 / 2)
+            .unwrap();
+        drop(tx_index);
+
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        assert_eq!(
+            file_len(dir.path(), TX_INDEX_FILE),
+            8 * TX_INDEX_ENTRY_LEN as u64
+        );
+        assert_eq!(store.latest_height().await, Ok(3));
+        let hash = blocks[2].header.hash();
+        assert_eq!(store.block_by_hash(hash).await, Ok(blocks[2].clone()));
+        assert_eq!(store.header_by_hash(hash).await, Ok(blocks[2].header));
+        for (i, block_height, tx_index) in [(0, 0, 0), (5, 2, 1), (7, 3, 1)] {
+            assert_eq!(
+                store.transaction_by_id(tx(i).tx_id).await,
+                Ok(TransactionLocation {
+                    block_height,
+                    tx_index
+                })
+            );
+        }
+
+        // The replaced block and its transactions are no longer found
+        let mut replacement = block(2);
+        replacement.header.consensus_fields.nonce = 1;
+        store.append(&replacement).unwrap();
+        assert_eq!(
+            store.block_by_hash(hash).await,
+            Err(ServerError::UnknownBlock(hash))
+        );
+        assert_eq!(
+            store.transaction_by_id(tx(5).tx_id).await,
+            Err(ServerError::UnknownTransaction(tx(5).tx_id))
+        );
+    }
+
+    #[test]
+    fn refuses_stores_of_another_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        store.append(&block(0)).unwrap();
+        drop(store);
+        assert!(DiskBlockStore::open(dir.path()).is_ok());
+
+        let mut format = fs::read(dir.path().join(FORMAT_FILE)).unwrap();
+        format[8..].copy_from_slice(&1u32.to_le_bytes());
+        fs::write(dir.path().join(FORMAT_FILE), &format).unwrap();
+        let error = DiskBlockStore::open(dir.path()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("has version 1"));
+
+        // A store written before the versioning
+        fs::remove_file(dir.path().join(FORMAT_FILE)).unwrap();
+        let error = DiskBlockStore::open(dir.path()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("predates"));
+    }
+
+    #[tokio::test]
+    async fn checks_the_indexed_hash_of_the_blocks_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        store.append(&block(0)).unwrap();
+        store.append(&block(1)).unwrap();
+        drop(store);
+
+        // Flip a bit of the hash in the entry of the first block
+        let mut index = fs::read(dir.path().join(INDEX_FILE)).unwrap();
+        index[12] ^= 1;
+        fs::write(dir.path().join(INDEX_FILE), &index).unwrap();
+
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.get(0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            store.block_headers(0..1).await,
+            Err(ServerError::Unavailable)
+        );
+    }
+
+    #[test]
+    fn failed_append_leaves_the_store_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        store.append(&block(0)).unwrap();
+        let log_len = file_len(dir.path(), LOG_FILE);
+
+        // The index can no longer be written
+        let read_only = File::open(dir.path().join(INDEX_FILE)).unwrap();
+        let writable = std::mem::replace(&mut store.inner.lock().unwrap().index_file, read_only);
+        assert!(store.append(&block(1)).is_err());
+        assert_eq!(store.get(1).unwrap(), None);
+        assert_eq!(store.len(), 1);
+        assert_eq!(file_len(dir.path(), LOG_FILE), log_len);
+
+        // A retry writes a single record
+        store.inner.lock().unwrap().index_file = writable;
+        store.append(&block(1)).unwrap();
+        drop(store);
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(1).unwrap(), Some(block(1)));
+        assert_eq!(file_len(dir.path(), LOG_FILE), 2 * log_len);
+    }
+
+    #[test]
+    fn later_append_replaces_a_height() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        store.append(&block(0)).unwrap();
+        let mut replacement = block(0);
+        replacement.transactions.clear();
+        store.append(&replacement).unwrap();
+        drop(store);
+
+        let store = DiskBlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(0).unwrap(), Some(replacement));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::merkle::merkle_root;
+use crate::server::*;
+use crate::state::WorldState;
+use ed25519_dalek::SigningKey;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::BTreeMap;
+
+/// The distribution of the number of transactions per block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxCount {
+    Fixed(usize),
+    /// Uniformly distributed between `min` and `max` included.
+    Uniform {
+        min: usize,
+        max: usize,
+    },
+    /// Poisson distributed around `mean`, bursts included.
+    Poisson {
+        mean: f64,
+    },
+}
+
+/// The chains built by [`ChainGenerator`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    /// Seed of the keys and of the random workload.
+    pub seed: u64,
+    /// Number of funded accounts sending and receiving the transactions.
+    pub accounts: usize,
+    pub initial_balance: u64,
+    pub tx_count: TxCount,
+    /// Difficulty the headers are mined at.
+    pub difficulty: u32,
+    /// Heights of the blocks made invalid, with the reason they fail with.
+    ///
+    /// `InsufficientBalance` and `BadNonce` are only detected with the world
+    /// state, e.g. by a [`crate::chain::BlockTree`] or
+    /// [`crate::blocks::Blocks::build_blocks_sequential_on`]. The invalid transaction
+    /// is not applied to the state of the generator, the next blocks execute
+    /// as if the block had only its valid transactions.
+    pub faults: BTreeMap<u32, StateTransitionReason>,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            seed: 0,
+            accounts: 16,
+            initial_balance: 1_000_000,
+            tx_count: TxCount::Uniform { min: 0, max: 8 },
+            difficulty: 0,
+            faults: BTreeMap::new(),
+        }
+    }
+}
+
+/// The key of the account at `index` of the generator seeded with `seed`
+pub fn signing_key(seed: u64, index: usize) -> SigningKey {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(index as u64 + 1);
+    SigningKey::generate(&mut rng)
+}
+
+///
+/// Seedable generator of valid chains of signed transfers
+///
+/// It is an endless iterator over the blocks of the chain from the genesis
+/// block, the same configuration always gives the same blocks.
+#[derive(Debug)]
+pub struct ChainGenerator {
+    config: GeneratorConfig,
+    rng: ChaCha8Rng,
+    keys: Vec<SigningKey>,
+    /// The public keys of `keys`.
+    accounts: Vec<Account>,
+    genesis_state: WorldState,
+    /// The world state after the last generated block.
+    state: WorldState,
+    next_height: u32,
+    parent_hash: BlockHash,
+}
+
+impl ChainGenerator {
+    pub fn new(config: GeneratorConfig) -> Self {
+        let keys: Vec<SigningKey> = (0..config.accounts)
+            .map(|index| signing_key(config.seed, index))
+            .collect();
+        let accounts: Vec<Account> = keys
+            .iter()
+            .map(|key| Account(key.verifying_key().to_bytes()))
+            .collect();
+        let genesis_state: WorldState = accounts
+            .iter()
+            .map(|account| (*account, config.initial_balance))
+            .collect();
+        ChainGenerator {
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            config,
+            keys,
+            accounts,
+            state: genesis_state.clone(),
+            genesis_state,
+            next_height: 0,
+            parent_hash: BlockHash::default(),
+        }
+    }
+
+    /// The keys of the funded accounts
+    pub fn keys(&self) -> &[SigningKey] {
+        &self.keys
+    }
+
+    /// The world state before the genesis block
+    pub fn genesis_state(&self) -> &WorldState {
+        &self.genesis_state
+    }
+
+    /// The world state after the last generated block
+    pub fn state(&self) -> &WorldState {
+        &self.state
+    }
+
+    fn tx_count(&mut self) -> usize {
+        match self.config.tx_count {
+            TxCount::Fixed(count) => count,
+            TxCount::Uniform { min, max } => self.rng.gen_range(min..=max.max(min)),
+            TxCount::Poisson { mean } => {
+                // Knuth's algorithm, fine for the small means of a block
+                let limit = (-mean).exp();
+                let mut count = 0;
+                let mut product: f64 = self.rng.gen();
+                while product > limit {
+                    count += 1;
+                    product *= self.rng.gen::<f64>();
+                }
+                count
+            }
+        }
+    }
+
+    /// A transfer of a random account that can pay for it, applied to the state
+    fn transfer(&mut self) -> Option<Transaction> {
+        let fee = self.rng.gen_range(1..=10);
+        let payers: Vec<usize> = (0..self.keys.len())
+            .filter(|&i| self.balance(i) > fee)
+            .collect();
+        let sender = *payers.choose(&mut self.rng)?;
+        let recipient = self.rng.gen_range(0..self.keys.len());
+        let amount = self
+            .rng
+            .gen_range(1..=(self.balance(sender) - fee).div_ceil(10));
+        let transaction = self.signed(sender, recipient, amount, fee, 0);
+        self.state
+            .apply_transaction(&transaction)
+            .expect("generated transfers are valid");
+        Some(transaction)
+    }
+
+    fn balance(&self, index: usize) -> u64 {
+        self.state.balance(&self.accounts[index])
+    }
+
+    /// The transfer signed by the account at `sender`, with its next nonce
+    /// plus `nonce_offset`
+    fn signed(
+        &self,
+        sender: usize,
+        recipient: usize,
+        amount: u64,
+        fee: u64,
+        nonce_offset: u64,
+    ) -> Transaction {
+        let key = &self.keys[sender];
+        let fields = TransactionFields {
+            sender: self.accounts[sender],
+            recipient: self.accounts[recipient],
+            amount,
+            fee,
+            nonce: self.state.nonce(&self.accounts[sender]) + nonce_offset,
+        };
+        Transaction::sign(fields, key)
+    }
+
+    /// The next block of the chain, made invalid when a fault is configured at its height
+    pub fn next_block(&mut self) -> Block {
+        let block_height = self.next_height;
+        let fault = self.config.faults.get(&block_height).copied();
+        let count = self.tx_count();
+        let mut transactions: Vec<Transaction> =
+            (0..count).filter_map(|_| self.transfer()).collect();
+
+        let index = self.rng.gen_range(0..=transactions.len());
+        let sender = self.rng.gen_range(0..self.keys.len());
+        match fault {
+            Some(StateTransitionReason::InvalidSignature) => {
+                let mut forged = self.signed(sender, 0, 1, 1, 0);
+                forged.signature.0[0] ^= 0xff;
+                transactions.insert(index, forged);
+            }
+            // Last, so that the earlier transactions of the sender do not
+            // make it fail for another reason
+            Some(StateTransitionReason::InsufficientBalance) => {
+                let amount = self.balance(sender) + 1;
+                transactions.push(self.signed(sender, 0, amount, 1, 0));
+            }
+            Some(StateTransitionReason::BadNonce) => {
+                transactions.push(self.signed(sender, 0, 1, 1, 1));
+            }
+            _ => {}
+        }
+
+        let mut header = BlockHeader {
+            block_height,
+            parent_hash: self.parent_hash,
+            transactions_root: merkle_root(&transactions),
+            consensus_fields: ConsensusFields {
+                difficulty: self.config.difficulty,
+                nonce: 0,
+            },
+        };
+        match fault {
+            Some(StateTransitionReason::InvalidHeader) => {
+                // Mine until the header misses the difficulty it claims
+                header.consensus_fields.difficulty += 8;
+                while header.verify() {
+                    header.consensus_fields.nonce += 1;
+                }
+            }
+            _ => header.mine(),
+        }
+        if fault == Some(StateTransitionReason::TransactionsRootMismatch) {
+            // Sealed on the right root, so only the body is wrong
+            transactions.push(self.signed(sender, 0, 1, 1, 0));
+        }
+
+        self.next_height += 1;
+        self.parent_hash = header.hash();
+        Block {
+            header,
+            transactions,
+        }
+    }
+}
+
+impl Iterator for ChainGenerator {
+    type Item = Block;
+
+    fn next(&mut self) -> Option<Block> {
+        Some(self.next_block())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{BlockTree, ChainError, LongestChain};
+
+    #[test]
+    fn same_seed_gives_the_same_chain() {
+        let config = GeneratorConfig {
+            seed: 3,
+            tx_count: TxCount::Poisson { mean: 3.0 },
+            ..Default::default()
+        };
+        let first: Vec<Block> = ChainGenerator::new(config.clone()).take(20).collect();
+        let second: Vec<Block> = ChainGenerator::new(config).take(20).collect();
+        assert_eq!(first, second);
+        let other: Vec<Block> = ChainGenerator::new(GeneratorConfig {
+            seed: 4,
+            ..Default::default()
+        })
+        .take(20)
+        .collect();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn generates_valid_linked_blocks() {
+        let config = GeneratorConfig {
+            tx_count: TxCount::Fixed(5),
+            difficulty: 3,
+            ..Default::default()
+        };
+        let mut generator = ChainGenerator::new(config);
+        let mut state = generator.genesis_state().clone();
+        let blocks: Vec<Block> = generator.by_ref().take(10).collect();
+        let mut tx_ids = std::collections::HashSet::new();
+        for (height, pair) in blocks.windows(2).enumerate() {
+            assert_eq!(pair[1].header.parent_hash, pair[0].header.hash());
+            assert_eq!(pair[0].header.block_height, height as u32);
+        }
+        for block in &blocks {
+            assert!(block.header.verify());
+            assert_eq!(block.transactions.len(), 5);
+            assert_eq!(
+                merkle_root(&block.transactions),
+                block.header.transactions_root
+            );
+            state.apply_block(block).unwrap();
+            tx_ids.extend(block.transactions.iter().map(|tx| tx.tx_id));
+        }
+        assert_eq!(tx_ids.len(), 50);
+        assert_eq!(&state, generator.state());
+    }
+
+    #[test]
+    fn injects_faults_at_chosen_heights() {
+        for reason in [
+            StateTransitionReason::InvalidHeader,
+            StateTransitionReason::TransactionsRootMismatch,
+            StateTransitionReason::InvalidSignature,
+            StateTransitionReason::InsufficientBalance,
+            StateTransitionReason::BadNonce,
+        ] {
+            let config = GeneratorConfig {
+                faults: [(3, reason)].into_iter().collect(),
+                ..Default::default()
+            };
+            let mut generator = ChainGenerator::new(config);
+            let genesis = generator.next_block();
+            let mut state = generator.genesis_state().clone();
+            state.apply_block(&genesis).unwrap();
+            let mut tree = BlockTree::new(genesis, state, LongestChain);
+            for block in generator.by_ref().take(2) {
+                tree.insert(block).unwrap();
+            }
+            match tree.insert(generator.next_block()) {
+                Err(ChainError::StateTransition(e)) => {
+                    assert_eq!((e.block_height, e.reason), (3, reason))
+                }
+                other => panic!("expected {:?}, got {:?}", reason, other),
+            }
+        }
+    }
+}
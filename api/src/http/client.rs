@@ -0,0 +1,295 @@
+use crate::merkle::{MerkleProof, ProofError};
+use crate::server::*;
+use async_trait::async_trait;
+use core::ops::Range;
+use futures::stream::{BoxStream, StreamExt};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+
+///
+/// `ServerAPI` client for the routes of [`crate::http::routes`]
+///
+/// The inner `reqwest::Client` keeps a pool of connections, so clones of the
+/// client reuse the same connections to the server.
+#[derive(Debug, Clone)]
+pub struct HttpServerApi {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpServerApi {
+    /// Create a client for the server at `base_url`, e.g. `http://localhost:8000`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_client(base_url, reqwest::Client::new())
+    }
+
+    /// Create a client sharing the connection pool of `client`
+    pub fn with_client(base_url: impl Into<String>, client: reqwest::Client) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        HttpServerApi { client, base_url }
+    }
+
+    /// Fetch the proof that the transaction at `tx_index` belongs to the block at `block_height`
+    ///
+    /// Check it against a header with [`crate::merkle::verify_inclusion`].
+    pub async fn inclusion_proof(
+        &self,
+        block_height: u32,
+        tx_index: usize,
+    ) -> Result<MerkleProof, ProofError> {
+        let path = format!("proof/{}/{}", block_height, tx_index);
+        let range = block_height..block_height + 1;
+        self.get(&path, |status, retry_after| {
+            error_from_status(status, range, retry_after)
+        })
+        .await
+    }
+
+    async fn get_range<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        block_height_range: Range<u32>,
+    ) -> Result<T, ServerError> {
+        let path = format!(
+            "{}/{}/{}",
+            endpoint, block_height_range.start, block_height_range.end
+        );
+        self.get(&path, |status, retry_after| {
+            error_from_status(status, block_height_range, retry_after)
+        })
+        .await
+    }
+
+    /// Get a lookup answered with `not_found` when nothing matches
+    async fn get_one<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        not_found: ServerError,
+    ) -> Result<T, ServerError> {
+        self.get(path, |status, retry_after| match status {
+            StatusCode::NOT_FOUND => not_found,
+            // The other codes do not depend on the range
+            status => error_from_status(status, 0..0, retry_after),
+        })
+        .await
+    }
+
+    /// Stream the newline-delimited `Result`s answered for the range, see [`crate::http::routes`]
+    ///
+    /// The body is read as the stream is polled, so the server is only
+    /// asked for more items once the previous ones are consumed.
+    fn get_lines<T: DeserializeOwned + Send + 'static>(
+        &self,
+        endpoint: &str,
+        block_height_range: Range<u32>,
+    ) -> BoxStream<'static, Result<T, ServerError>> {
+        let path = format!(
+            "stream/{}/{}/{}",
+            endpoint, block_height_range.start, block_height_range.end
+        );
+        let lines = Lines {
+            client: self.clone(),
+            path,
+            block_height_range,
+            response: None,
+            buffer: vec![],
+            done: false,
+        };
+        futures::stream::unfold(lines, |mut lines| async move {
+            if lines.done {
+                return None;
+            }
+            let item = lines.next_item().await;
+            lines.done = !matches!(item, Some(Ok(_)));
+            item.map(|item| (item, lines))
+        })
+        .boxed()
+    }
+
+    /// Get `path` and decode the answer, or the error `E` the server failed with
+    ///
+    /// `fallback` builds the error from the status code and the `Retry-After`
+    /// header when the body is not an error.
+    async fn get<T, E>(
+        &self,
+        path: &str,
+        fallback: impl FnOnce(StatusCode, Option<u64>) -> ServerError,
+    ) -> Result<T, E>
+    where
+        T: DeserializeOwned,
+        E: DeserializeOwned + From<ServerError>,
+    {
+        let body = self
+            .send::<E>(path, fallback)
+            .await?
+            .bytes()
+            .await
+            .map_err(|e| ServerError::Transport(e.to_string()))?;
+        serde_json::from_slice(&body).map_err(|e| ServerError::Transport(e.to_string()).into())
+    }
+
+    /// Get `path`, failing with the error `E` the server answered with, see [`HttpServerApi::get`]
+    async fn send<E>(
+        &self,
+        path: &str,
+        fallback: impl FnOnce(StatusCode, Option<u64>) -> ServerError,
+    ) -> Result<reqwest::Response, E>
+    where
+        E: DeserializeOwned + From<ServerError>,
+    {
+        let url = format!("{}/{}", self.base_url, path);
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| ServerError::Transport(e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| ServerError::Transport(e.to_string()))?;
+        // The server sends the error it failed with, fall back on the status
+        // code when the body is not one (e.g. a proxy answered instead).
+        match serde_json::from_slice(&body) {
+            Ok(e) => Err(e),
+            Err(_) => Err(fallback(status, retry_after).into()),
+        }
+    }
+}
+
+/// The state of a stream of [`HttpServerApi::get_lines`].
+struct Lines {
+    client: HttpServerApi,
+    path: String,
+    block_height_range: Range<u32>,
+    response: Option<reqwest::Response>,
+    /// Bytes received after the last complete line.
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl Lines {
+    async fn next_item<T: DeserializeOwned>(&mut self) -> Option<Result<T, ServerError>> {
+        let transport = |e: &dyn std::fmt::Display| ServerError::Transport(e.to_string());
+        loop {
+            let response = match &mut self.response {
+                Some(response) => response,
+                None => {
+                    let range = self.block_height_range.clone();
+                    let sent = self
+                        .client
+                        .send(&self.path, |status, retry_after| {
+                            error_from_status(status, range, retry_after)
+                        })
+                        .await;
+                    match sent {
+                        Ok(response) => self.response.insert(response),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+            };
+            if let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                return Some(match serde_json::from_slice(&line) {
+                    Ok(item) => item,
+                    Err(e) => Err(transport(&e)),
+                });
+            }
+            match response.chunk().await {
+                Ok(Some(bytes)) => self.buffer.extend_from_slice(&bytes),
+                Ok(None) if self.buffer.is_empty() => return None,
+                Ok(None) => return Some(Err(transport(&"truncated line"))),
+                Err(e) => return Some(Err(transport(&e))),
+            }
+        }
+    }
+}
+
+/// `retry_after` is the `Retry-After` header in seconds. The range is the
+/// requested one since the status code does not tell more, and the maximum
+/// range length is reported as `0` as it is unknown.
+fn error_from_status(
+    status: StatusCode,
+    block_height_range: Range<u32>,
+    retry_after: Option<u64>,
+) -> ServerError {
+    match status {
+        StatusCode::NOT_FOUND => ServerError::MissingHeight(block_height_range.start),
+        StatusCode::BAD_REQUEST => ServerError::InvalidRange {
+            start: block_height_range.start,
+            end: block_height_range.end,
+        },
+        StatusCode::PAYLOAD_TOO_LARGE => ServerError::RangeTooLarge {
+            requested: block_height_range.len() as u32,
+            max: 0,
+        },
+        StatusCode::TOO_MANY_REQUESTS => ServerError::Throttled {
+            retry_after_ms: retry_after.unwrap_or(1) * 1000,
+        },
+        s if s.is_server_error() => ServerError::Unavailable,
+        s => ServerError::Transport(format!("unexpected status {}", s)),
+    }
+}
+
+#[async_trait]
+impl ServerAPI for HttpServerApi {
+    async fn block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<BlockHeader>, ServerError> {
+        self.get_range("headers", block_height_range).await
+    }
+
+    async fn block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<Vec<Transaction>>, ServerError> {
+        self.get_range("transactions", block_height_range).await
+    }
+
+    // Streamed from the server instead of fetching each chunk separately
+    fn stream_block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> BoxStream<'_, Result<BlockHeader, ServerError>> {
+        self.get_lines("headers", block_height_range)
+    }
+
+    fn stream_block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> BoxStream<'_, Result<Vec<Transaction>, ServerError>> {
+        self.get_lines("transactions", block_height_range)
+    }
+
+    async fn latest_height(&self) -> Result<u32, ServerError> {
+        self.get_one("height", ServerError::MissingHeight(0)).await
+    }
+
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+        let path = format!("header/{}", hash);
+        self.get_one(&path, ServerError::UnknownBlock(hash)).await
+    }
+
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+        let path = format!("block/{}", hash);
+        self.get_one(&path, ServerError::UnknownBlock(hash)).await
+    }
+
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError> {
+        let path = format!("transaction/{}", tx_id);
+        self.get_one(&path, ServerError::UnknownTransaction(tx_id))
+            .await
+    }
+}
@@ -0,0 +1,142 @@
+use crate::merkle::{prove_inclusion, ProofError};
+use crate::server::*;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::Serialize;
+use std::sync::Arc;
+use warp::http::header::{self, HeaderValue};
+use warp::http::StatusCode;
+use warp::reply::Reply;
+
+/// The status code the server answers with for a given error.
+///
+/// [`crate::http::client::HttpServerApi`] maps these codes back to the same variants.
+pub fn status_code(error: &ServerError) -> StatusCode {
+    match error {
+        ServerError::MissingHeight(_)
+        | ServerError::UnknownBlock(_)
+        | ServerError::UnknownTransaction(_) => StatusCode::NOT_FOUND,
+        ServerError::InvalidRange { .. } => StatusCode::BAD_REQUEST,
+        ServerError::RangeTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        ServerError::Throttled { .. } => StatusCode::TOO_MANY_REQUESTS,
+        ServerError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ServerError::Transport(_) | ServerError::NoQuorum { .. } => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn reply<T: Serialize>(result: Result<T, ServerError>) -> warp::reply::Response {
+    match result {
+        Ok(items) => warp::reply::json(&items).into_response(),
+        Err(e) => {
+            let mut response =
+                warp::reply::with_status(warp::reply::json(&e), status_code(&e)).into_response();
+            if let ServerError::Throttled { retry_after_ms } = e {
+                let seconds = retry_after_ms.div_ceil(1000);
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+            }
+            response
+        }
+    }
+}
+
+pub async fn get_block_headers<S: ServerAPI>(
+    start: u32,
+    end: u32,
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply(server.block_headers(start..end).await))
+}
+
+pub async fn get_block_transactions<S: ServerAPI>(
+    start: u32,
+    end: u32,
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply(server.block_transactions(start..end).await))
+}
+
+/// Answer the items of `items` one JSON `Result` per line as they come
+///
+/// The first item is awaited before answering, so that an error of the
+/// first request gets its own status code like the other endpoints.
+async fn stream_reply<T: Serialize + Send + 'static>(
+    mut items: BoxStream<'static, Result<T, ServerError>>,
+) -> warp::reply::Response {
+    let first = match items.next().await {
+        Some(Err(e)) => return reply::<T>(Err(e)),
+        first => first,
+    };
+    let lines = stream::iter(first).chain(items).map(|item| {
+        let mut line = serde_json::to_vec(&item)?;
+        line.push(b'\n');
+        Ok::<_, serde_json::Error>(line)
+    });
+    let mut response = warp::reply::Response::new(warp::hyper::Body::wrap_stream(lines));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    response
+}
+
+pub async fn stream_block_headers<S: ServerAPI + Send + Sync + 'static>(
+    start: u32,
+    end: u32,
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(stream_reply(chunked_block_headers(Arc::new(server), start..end)).await)
+}
+
+pub async fn stream_block_transactions<S: ServerAPI + Send + Sync + 'static>(
+    start: u32,
+    end: u32,
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(stream_reply(chunked_block_transactions(Arc::new(server), start..end)).await)
+}
+
+pub async fn get_latest_height<S: ServerAPI>(
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply(server.latest_height().await))
+}
+
+pub async fn get_header_by_hash<S: ServerAPI>(
+    hash: BlockHash,
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply(server.header_by_hash(hash).await))
+}
+
+pub async fn get_block_by_hash<S: ServerAPI>(
+    hash: BlockHash,
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply(server.block_by_hash(hash).await))
+}
+
+pub async fn get_transaction_by_id<S: ServerAPI>(
+    tx_id: TransactionId,
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply(server.transaction_by_id(tx_id).await))
+}
+
+pub async fn get_inclusion_proof<S: ServerAPI>(
+    height: u32,
+    tx_index: usize,
+    server: S,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = match prove_inclusion(&server, height, tx_index).await {
+        Ok(proof) => warp::reply::json(&proof).into_response(),
+        Err(e) => {
+            let status = match &e {
+                ProofError::Server(e) => status_code(e),
+                ProofError::MissingTransaction { .. } => StatusCode::NOT_FOUND,
+            };
+            warp::reply::with_status(warp::reply::json(&e), status).into_response()
+        }
+    };
+    Ok(response)
+}
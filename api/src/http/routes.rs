@@ -0,0 +1,177 @@
+use crate::http::handlers;
+use crate::limits::LimitedServerApi;
+use crate::server::*;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use warp::Filter;
+
+///
+/// Expose a `ServerAPI` over HTTP
+///
+/// `GET /headers/{start}/{end}` and `GET /transactions/{start}/{end}` answer
+/// `block_headers(start..end)` and `block_transactions(start..end)` in JSON.
+/// `GET /stream/headers/{start}/{end}` and `GET /stream/transactions/{start}/{end}`
+/// stream the same items as newline-delimited JSON, one `Result` per line,
+/// fetching them [`STREAM_CHUNK_LEN`] heights at a time.
+/// `GET /height`, `GET /header/{hash}`, `GET /block/{hash}` and
+/// `GET /transaction/{tx_id}` answer the lookups of the same names, with the
+/// hash and the id as `0x`-prefixed hex. `GET /proof/{height}/{tx_index}`
+/// answers the Merkle proof of inclusion of a transaction, see
+/// [`crate::merkle::prove_inclusion`].
+pub fn routes<S>(
+    server: S,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Clone + Send + Sync + 'static,
+{
+    serve(warp::any().map(move || server.clone()))
+}
+
+///
+/// Same as [`routes`], charging each call to the quota of the remote IP address
+///
+pub fn limited_routes<S>(
+    server: LimitedServerApi<S>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+{
+    serve(warp::addr::remote().map(move |addr: Option<SocketAddr>| {
+        server.for_client(addr.map(|a| a.ip().to_string()).unwrap_or_default())
+    }))
+}
+
+fn serve<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    get_block_headers(server.clone())
+        .or(get_block_transactions(server.clone()))
+        .or(stream_block_headers(server.clone()))
+        .or(stream_block_transactions(server.clone()))
+        .or(get_latest_height(server.clone()))
+        .or(get_header_by_hash(server.clone()))
+        .or(get_block_by_hash(server.clone()))
+        .or(get_transaction_by_id(server.clone()))
+        .or(get_inclusion_proof(server))
+}
+
+pub fn get_block_headers<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("headers" / u32 / u32)
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::get_block_headers)
+}
+
+pub fn get_block_transactions<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("transactions" / u32 / u32)
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::get_block_transactions)
+}
+
+pub fn stream_block_headers<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("stream" / "headers" / u32 / u32)
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::stream_block_headers)
+}
+
+pub fn stream_block_transactions<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("stream" / "transactions" / u32 / u32)
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::stream_block_transactions)
+}
+
+pub fn get_latest_height<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("height")
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::get_latest_height)
+}
+
+pub fn get_header_by_hash<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("header" / BlockHash)
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::get_header_by_hash)
+}
+
+pub fn get_block_by_hash<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("block" / BlockHash)
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::get_block_by_hash)
+}
+
+pub fn get_transaction_by_id<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("transaction" / TransactionId)
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::get_transaction_by_id)
+}
+
+pub fn get_inclusion_proof<S, F>(
+    server: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+where
+    S: ServerAPI + Send + Sync + 'static,
+    F: Filter<Extract = (S,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    warp::path!("proof" / u32 / usize)
+        .and(warp::get())
+        .and(server)
+        .and_then(handlers::get_inclusion_proof)
+}
@@ -1,2 +1,17 @@
 pub mod blocks;
+pub mod bytes;
+pub mod cache;
+pub mod chain;
+pub mod chaos;
+pub mod codec;
+pub mod disk_store;
+pub mod generator;
+pub mod http;
+pub mod limits;
+pub mod mempool;
+pub mod merkle;
+pub mod producer;
+pub mod quorum;
 pub mod server;
+pub mod state;
+pub mod store;
@@ -0,0 +1,286 @@
+use crate::server::*;
+use async_trait::async_trait;
+use core::ops::Range;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The identifier the quota of a client is tracked under, e.g. its IP address.
+pub type ClientId = String;
+
+/// The limits enforced by [`LimitedServerApi`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Largest number of heights one call may request.
+    pub max_range_len: u32,
+    /// Number of calls a client may make per `window`, `0` throttles every call.
+    pub quota: u32,
+    pub window: Duration,
+}
+
+/// Token bucket of one client, refilled continuously at `quota / window`.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    /// Add the tokens refilled since the last call, up to `quota`
+    fn refill(&mut self, now: Instant, quota: f64, per_second: f64) {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(quota);
+        self.refilled_at = now;
+    }
+}
+
+/// The buckets of the clients, a full bucket is the same as none.
+#[derive(Debug)]
+struct Buckets {
+    clients: HashMap<ClientId, Bucket>,
+    swept_at: Instant,
+}
+
+///
+/// `ServerAPI` decorator capping the range length and the request rate per client
+///
+/// All the clones share the same quotas; [`LimitedServerApi::for_client`]
+/// returns a clone that charges the calls to another client.
+#[derive(Debug)]
+pub struct LimitedServerApi<S> {
+    inner: Arc<S>,
+    limits: Limits,
+    buckets: Arc<Mutex<Buckets>>,
+    client: ClientId,
+}
+
+impl<S> Clone for LimitedServerApi<S> {
+    fn clone(&self) -> Self {
+        LimitedServerApi {
+            inner: self.inner.clone(),
+            limits: self.limits,
+            buckets: self.buckets.clone(),
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<S: ServerAPI> LimitedServerApi<S> {
+    pub fn new(inner: S, limits: Limits) -> Self {
+        LimitedServerApi {
+            inner: Arc::new(inner),
+            limits,
+            buckets: Arc::new(Mutex::new(Buckets {
+                clients: HashMap::new(),
+                swept_at: Instant::now(),
+            })),
+            client: ClientId::new(),
+        }
+    }
+
+    /// The same server, charging the calls to `client`
+    pub fn for_client(&self, client: impl Into<ClientId>) -> Self {
+        LimitedServerApi {
+            client: client.into(),
+            ..self.clone()
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Check the range length, then take one call from the quota of the client
+    fn admit(&self, block_height_range: &Range<u32>) -> Result<(), ServerError> {
+        let requested = block_height_range
+            .end
+            .saturating_sub(block_height_range.start);
+        if requested > self.limits.max_range_len {
+            return Err(ServerError::RangeTooLarge {
+                requested,
+                max: self.limits.max_range_len,
+            });
+        }
+        self.charge()
+    }
+
+    /// Take one call from the quota of the client
+    ///
+    /// Once per `window`, the buckets refilled to the full quota are
+    /// dropped, so only the clients seen recently are kept.
+    fn charge(&self) -> Result<(), ServerError> {
+        if self.limits.quota == 0 {
+            return Err(ServerError::Throttled {
+                retry_after_ms: self.limits.window.as_millis() as u64,
+            });
+        }
+        let quota = self.limits.quota as f64;
+        let per_second = quota / self.limits.window.as_secs_f64();
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.duration_since(buckets.swept_at) >= self.limits.window {
+            buckets.clients.retain(|_, bucket| {
+                bucket.refill(now, quota, per_second);
+                bucket.tokens < quota
+            });
+            buckets.swept_at = now;
+        }
+        let bucket = buckets
+            .clients
+            .entry(self.client.clone())
+            .or_insert(Bucket {
+                tokens: quota,
+                refilled_at: now,
+            });
+        bucket.refill(now, quota, per_second);
+        if bucket.tokens < 1.0 {
+            let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) / per_second);
+            return Err(ServerError::Throttled {
+                retry_after_ms: retry_after.as_millis() as u64 + 1,
+            });
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: ServerAPI + Send + Sync> ServerAPI for LimitedServerApi<S> {
+    async fn block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<BlockHeader>, ServerError> {
+        self.admit(&block_height_range)?;
+        self.inner.block_headers(block_height_range).await
+    }
+
+    async fn block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<Vec<Transaction>>, ServerError> {
+        self.admit(&block_height_range)?;
+        self.inner.block_transactions(block_height_range).await
+    }
+
+    async fn latest_height(&self) -> Result<u32, ServerError> {
+        self.charge()?;
+        self.inner.latest_height().await
+    }
+
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+        self.charge()?;
+        self.inner.header_by_hash(hash).await
+    }
+
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+        self.charge()?;
+        self.inner.block_by_hash(hash).await
+    }
+
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError> {
+        self.charge()?;
+        self.inner.transaction_by_id(tx_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::BlockStore;
+
+    fn limited(quota: u32, window: Duration) -> LimitedServerApi<BlockStore> {
+        let store = (0..100)
+            .map(|height| Block {
+                header: BlockHeader {
+                    block_height: height,
+                    ..Default::default()
+                },
+                transactions: vec![],
+            })
+            .collect();
+        LimitedServerApi::new(
+            store,
+            Limits {
+                max_range_len: 10,
+                quota,
+                window,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn rejects_ranges_that_are_too_large() {
+        let server = limited(100, Duration::from_secs(1));
+        assert_eq!(server.block_headers(0..10).await.unwrap().len(), 10);
+        assert_eq!(
+            server.block_transactions(0..11).await,
+            Err(ServerError::RangeTooLarge {
+                requested: 11,
+                max: 10
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn throttles_each_client_separately() {
+        let server = limited(3, Duration::from_secs(60));
+        let alice = server.for_client("alice");
+        for _ in 0..3 {
+            alice.block_headers(0..1).await.unwrap();
+        }
+        match alice.block_headers(0..1).await {
+            Err(ServerError::Throttled { retry_after_ms }) => {
+                assert!(retry_after_ms > 0 && retry_after_ms <= 20_000)
+            }
+            other => panic!("expected a throttled error, got {:?}", other),
+        }
+        // Another client still has its quota, whichever clone it goes through
+        server.for_client("bob").block_headers(0..1).await.unwrap();
+        assert!(matches!(
+            server.for_client("alice").block_transactions(0..1).await,
+            Err(ServerError::Throttled { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn quota_refills_over_time() {
+        let server = limited(2, Duration::from_millis(40));
+        server.block_headers(0..1).await.unwrap();
+        server.block_headers(0..1).await.unwrap();
+        assert!(server.block_headers(0..1).await.is_err());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        server.block_headers(0..1).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn zero_quota_throttles_every_call() {
+        let server = limited(0, Duration::from_secs(1));
+        assert_eq!(
+            server.block_headers(0..1).await,
+            Err(ServerError::Throttled {
+                retry_after_ms: 1000
+            })
+        );
+        assert!(server.latest_height().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn forgets_idle_clients() {
+        let server = limited(2, Duration::from_millis(20));
+        for client in ["alice", "bob"] {
+            server.for_client(client).block_headers(0..1).await.unwrap();
+        }
+        assert_eq!(server.buckets.lock().unwrap().clients.len(), 2);
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        server
+            .for_client("carol")
+            .block_headers(0..1)
+            .await
+            .unwrap();
+        let buckets = server.buckets.lock().unwrap();
+        assert_eq!(buckets.clients.keys().collect::<Vec<_>>(), ["carol"]);
+    }
+}
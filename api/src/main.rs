@@ -1,67 +1,20 @@
-use api::blocks::Blocks;
-use api::server::{Block, BlockHeader, BlockList, ConsensusFields, Transaction, TransactionFields};
-use hex::FromHex;
+use api::blocks::{Blocks, BuildConfig};
+use api::generator::{ChainGenerator, GeneratorConfig, TxCount};
+use api::server::{Block, BlockList};
 use std::sync::Arc;
-use std::thread;
-use tokio::runtime::Runtime;
 
 #[tokio::main]
 async fn main() {
     // cargo bench can be used
 
-    let mut list_block = BlockList::new();
-
-
-   // Could have generated transactions randomly and much more to simulate real blocks performance
-
-    let transaction0 = Transaction {
-        tx_id: <[u8; 32]>::from_hex(
-            "1e6f77206973207468652074696d6520666f7220616c6c20676f6f64206d656e",
-        )
-        .expect("invalid tx_id"),
-        transaction_fields: TransactionFields {},
-    };
-    let transaction1 = Transaction {
-        tx_id: <[u8; 32]>::from_hex(
-            "2eef77206973207468652074696d6520666f7220616c6c20676f6f64206d656e",
-        )
-        .expect("invalid tx_id"),
-        transaction_fields: TransactionFields {},
+    // Signed transfers between funded accounts, about four per block
+    let config = GeneratorConfig {
+        tx_count: TxCount::Poisson { mean: 4.0 },
+        ..Default::default()
     };
-    let transaction2 = Transaction {
-        tx_id: <[u8; 32]>::from_hex(
-            "3eaf77206973207468652074696d6520666f7220616c6c20676f6f64206d656e",
-        )
-        .expect("invalid tx_id"),
-        transaction_fields: TransactionFields {},
-    };
-    let transaction3 = Transaction {
-        tx_id: <[u8; 32]>::from_hex(
-            "4eff77206973207468652074696d6520666f7220616c6c20676f6f64206d656e",
-        )
-        .expect("invalid tx_id"),
-        transaction_fields: TransactionFields {},
-    };
-    let mut transactions = Vec::new();
-    transactions.push(transaction0);
-    transactions.push(transaction1);
-    transactions.push(transaction2);
-    transactions.push(transaction3);
-
-    for i in 0..100000 {
-        let block_header = BlockHeader {
-            block_height: i,
-            consensus_fields: ConsensusFields {},
-        };
-        let block = Block {
-            header: block_header,
-            transactions: transactions.clone(),
-        };
-        list_block.insert_at_tail(block);
-    }
+    let list_block: BlockList = ChainGenerator::new(config).take(100000).collect();
 
     let arclist = Arc::new(list_block);
-    let arclist_cpy = arclist.clone();
 
     //
     // Generates blocks in parallel calling build_blocks_parallel
@@ -76,8 +29,7 @@ async fn main() {
     println!("---- End build blocks in parallel ----");
 
     //
-    // Generates blocks recursively backward  (for X depends on X -1 )
-    // (Might need to increase the local stack size)
+    // Generates blocks backward  (for X depends on X -1 )
     //
     println!("---- Builds blocks backward ----");
     let blcks: Vec<Block> = vec![];
@@ -86,26 +38,21 @@ async fn main() {
     assert_eq!(blocks_backward.expect("blocks list backward").len(), 10000);
     println!("---- End build blocks backward ----");
 
-
-    println!("---- Builds blocks backward (increase stack size) ----");
-    let builder = thread::Builder::new().stack_size(64 * 1024 * 1024);
-    let handler = builder
-        .spawn(move || {
-            let rt = Runtime::new().unwrap();
-            let blcks: Vec<Block> = vec![];
-            rt.block_on(async {
-                let blocks_backward = arclist_cpy.build_blocks_backward(blcks, 0..100000).await;
-                // println!("block parallel backward {:#?}", blocks_backward);
-                assert_eq!(blocks_backward.expect("blocks list backward").len(), 100000);
-                println!("---- End build blocks backward (increase stack size) ----");
-            })
-        })
-        .unwrap();
-    let _ = handler.join().unwrap();
+    //
+    // Generates the whole chain sequentially, on the default stack
+    //
+    println!("---- Builds blocks sequentially ----");
+    let blocks_sequential = arclist
+        .build_blocks_sequential(0..100000, BuildConfig::default())
+        .await;
+    assert_eq!(
+        blocks_sequential.expect("blocks list sequential").len(),
+        100000
+    );
+    println!("---- End build blocks sequentially ----");
 
     //
-    // Generates blocks recursively forward
-    // (Same as above, might need to increase the local stack size)
+    // Generates blocks forward, from the highest height to the lowest
     //
     println!("---- Builds blocks forward ----");
     let blcks: Vec<Block> = vec![];
@@ -114,7 +61,6 @@ async fn main() {
     assert_eq!(blocks_forward.expect("blocks list forward").len(), 10000);
     println!("---- End build blocks forward ----");
 
-
     // #[allow(dead_code)]
     // let headers = list_block.block_headers(1..6).await;
     // println!("block headers {:#?}", headers);
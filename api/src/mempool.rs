@@ -0,0 +1,304 @@
+use crate::server::*;
+use crate::state::WorldState;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fmt;
+
+/// The error returned when a transaction is not accepted in the mempool.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum MempoolError {
+    /// A transaction with the same `tx_id` is already pending.
+    AlreadyKnown(TransactionId),
+    /// A pending transaction of the sender has the same nonce and a fee at least as high.
+    Underpriced { pending: TransactionId },
+    /// The transaction can never execute on top of the chain.
+    Invalid(StateTransitionReason),
+    /// The mempool is full of transactions paying a higher fee.
+    Full,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::AlreadyKnown(tx_id) => {
+                write!(f, "Mempool error: transaction {} already pending", tx_id)
+            }
+            MempoolError::Underpriced { pending } => write!(
+                f,
+                "Mempool error: fee not higher than pending transaction {}",
+                pending
+            ),
+            MempoolError::Invalid(reason) => write!(f, "Mempool error: {}", reason),
+            MempoolError::Full => write!(f, "Mempool error: full"),
+        }
+    }
+}
+
+///
+/// Pending transactions waiting to be sealed in a block
+///
+/// Transactions are checked against the world state at the tip of the chain
+/// when submitted, and selected by decreasing fee in [`Mempool::select`].
+#[derive(Debug)]
+pub struct Mempool {
+    /// The world state at the tip of the chain.
+    state: WorldState,
+    transactions: HashMap<TransactionId, Transaction>,
+    /// `tx_id` of the pending transactions of each sender by nonce.
+    by_sender: HashMap<Account, BTreeMap<u64, TransactionId>>,
+    max_size: usize,
+}
+
+impl Mempool {
+    /// The empty mempool on top of the chain with the world state `state`
+    pub fn new(state: WorldState, max_size: usize) -> Self {
+        Mempool {
+            state,
+            transactions: HashMap::new(),
+            by_sender: HashMap::new(),
+            max_size,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn contains(&self, tx_id: &TransactionId) -> bool {
+        self.transactions.contains_key(tx_id)
+    }
+
+    pub fn state(&self) -> &WorldState {
+        &self.state
+    }
+
+    /// Add the transaction to the pending ones
+    ///
+    /// A pending transaction of the same sender and nonce is replaced when
+    /// the new one pays a higher fee. When the mempool is full, the
+    /// transaction paying the lowest fee is evicted to make room.
+    pub fn submit(&mut self, transaction: Transaction) -> Result<(), MempoolError> {
+        if self.contains(&transaction.tx_id) {
+            return Err(MempoolError::AlreadyKnown(transaction.tx_id));
+        }
+        transaction.execute().map_err(MempoolError::Invalid)?;
+        let fields = transaction.transaction_fields;
+        if fields.nonce < self.state.nonce(&fields.sender) {
+            return Err(MempoolError::Invalid(StateTransitionReason::BadNonce));
+        }
+        let cost = fields.amount.checked_add(fields.fee);
+        if cost.is_none_or(|cost| cost > self.state.balance(&fields.sender)) {
+            return Err(MempoolError::Invalid(
+                StateTransitionReason::InsufficientBalance,
+            ));
+        }
+
+        let replaced = self
+            .by_sender
+            .get(&fields.sender)
+            .and_then(|nonces| nonces.get(&fields.nonce))
+            .copied();
+        match replaced {
+            Some(pending) if self.transactions[&pending].transaction_fields.fee >= fields.fee => {
+                return Err(MempoolError::Underpriced { pending });
+            }
+            Some(pending) => self.remove(&pending),
+            None if self.transactions.len() >= self.max_size => {
+                let cheapest = self
+                    .transactions
+                    .values()
+                    .min_by_key(|tx| tx.transaction_fields.fee)
+                    .filter(|tx| tx.transaction_fields.fee < fields.fee)
+                    .map(|tx| tx.tx_id)
+                    .ok_or(MempoolError::Full)?;
+                self.remove(&cheapest);
+            }
+            None => {}
+        }
+        self.by_sender
+            .entry(fields.sender)
+            .or_default()
+            .insert(fields.nonce, transaction.tx_id);
+        self.transactions.insert(transaction.tx_id, transaction);
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) {
+        if let Some(transaction) = self.transactions.remove(tx_id) {
+            let fields = transaction.transaction_fields;
+            if let Some(nonces) = self.by_sender.get_mut(&fields.sender) {
+                nonces.remove(&fields.nonce);
+                if nonces.is_empty() {
+                    self.by_sender.remove(&fields.sender);
+                }
+            }
+        }
+    }
+
+    /// Up to `max` transactions that execute in order on top of the chain,
+    /// the ones paying the highest fee first
+    ///
+    /// The transactions of a sender are taken by increasing nonce, so a
+    /// transaction is only selected once the one before it is.
+    pub fn select(&self, max: usize) -> Vec<Transaction> {
+        let mut state = self.state.clone();
+        let next = |state: &WorldState, sender: &Account| {
+            let transaction = self.by_sender[sender]
+                .get(&state.nonce(sender))
+                .map(|tx_id| self.transactions[tx_id])?;
+            Some((
+                transaction.transaction_fields.fee,
+                Reverse(transaction.tx_id),
+            ))
+        };
+        // The next transaction of each sender, ties broken by `tx_id`
+        let mut heads: BinaryHeap<_> = self
+            .by_sender
+            .keys()
+            .filter_map(|sender| next(&state, sender))
+            .collect();
+        let mut selected = vec![];
+        while selected.len() < max {
+            let Some((_, Reverse(tx_id))) = heads.pop() else {
+                break;
+            };
+            let transaction = self.transactions[&tx_id];
+            // A sender that cannot pay is skipped with its later transactions
+            if state.apply_transaction(&transaction).is_ok() {
+                selected.push(transaction);
+                heads.extend(next(&state, &transaction.transaction_fields.sender));
+            }
+        }
+        selected
+    }
+
+    /// Execute the block added to the chain, and drop its transactions and
+    /// the ones that can no longer execute
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), StateTransitionError> {
+        self.state.apply_block(block)?;
+        for transaction in &block.transactions {
+            self.remove(&transaction.tx_id);
+        }
+        let stale: Vec<TransactionId> = self
+            .by_sender
+            .iter()
+            .flat_map(|(sender, nonces)| nonces.range(..self.state.nonce(sender)))
+            .map(|(_, tx_id)| *tx_id)
+            .collect();
+        for tx_id in stale {
+            self.remove(&tx_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::signing_key;
+
+    fn account(index: usize) -> Account {
+        signing_key(0, index).verifying_key().to_bytes().into()
+    }
+
+    fn transaction(sender: usize, nonce: u64, fee: u64) -> Transaction {
+        let fields = TransactionFields {
+            amount: 10,
+            fee,
+            nonce,
+            ..Default::default()
+        };
+        Transaction::sign(fields, &signing_key(0, sender))
+    }
+
+    fn mempool(max_size: usize) -> Mempool {
+        let state = [(account(1), 100), (account(2), 100)].into_iter().collect();
+        Mempool::new(state, max_size)
+    }
+
+    #[test]
+    fn validates_and_deduplicates() {
+        let mut mempool = mempool(10);
+        let tx = transaction(1, 0, 5);
+        mempool.submit(tx).unwrap();
+        assert_eq!(
+            mempool.submit(tx),
+            Err(MempoolError::AlreadyKnown(tx.tx_id))
+        );
+        assert_eq!(
+            mempool.submit(transaction(1, 0, 4)),
+            Err(MempoolError::Underpriced { pending: tx.tx_id })
+        );
+        assert_eq!(
+            mempool.submit(transaction(3, 0, 1)),
+            Err(MempoolError::Invalid(
+                StateTransitionReason::InsufficientBalance
+            ))
+        );
+
+        let replacement = transaction(1, 0, 6);
+        mempool.submit(replacement).unwrap();
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(&replacement.tx_id));
+    }
+
+    #[test]
+    fn selects_by_fee_in_nonce_order() {
+        let mut mempool = mempool(10);
+        for tx in [
+            transaction(1, 0, 1),
+            transaction(1, 1, 9),
+            transaction(2, 0, 5),
+            transaction(2, 2, 8),
+        ] {
+            mempool.submit(tx).unwrap();
+        }
+        // The nonce 2 of the second sender waits for its nonce 1
+        assert_eq!(
+            mempool.select(10),
+            vec![
+                transaction(2, 0, 5),
+                transaction(1, 0, 1),
+                transaction(1, 1, 9)
+            ]
+        );
+        assert_eq!(mempool.select(1).len(), 1);
+    }
+
+    #[test]
+    fn evicts_the_lowest_fee_when_full() {
+        let mut mempool = mempool(2);
+        mempool.submit(transaction(1, 0, 3)).unwrap();
+        mempool.submit(transaction(2, 0, 1)).unwrap();
+        assert_eq!(
+            mempool.submit(transaction(1, 1, 1)),
+            Err(MempoolError::Full)
+        );
+        mempool.submit(transaction(1, 1, 2)).unwrap();
+        assert!(!mempool.contains(&transaction(2, 0, 1).tx_id));
+    }
+
+    #[test]
+    fn drops_included_and_stale_transactions() {
+        let mut mempool = mempool(10);
+        let included = transaction(1, 0, 1);
+        let replaced = transaction(2, 0, 1);
+        for tx in [included, transaction(1, 1, 1), replaced] {
+            mempool.submit(tx).unwrap();
+        }
+        // Another producer included another transaction of the second sender
+        let block = Block {
+            header: BlockHeader::default(),
+            transactions: vec![included, transaction(2, 0, 7)],
+        };
+        mempool.apply_block(&block).unwrap();
+        assert_eq!(mempool.len(), 1);
+        assert_eq!(mempool.state().nonce(&account(2)), 1);
+        assert_eq!(mempool.select(10), vec![transaction(1, 1, 1)]);
+    }
+}
@@ -0,0 +1,320 @@
+use crate::bytes::hex_bytes;
+use crate::server::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+hex_bytes!(
+    /// A SHA-256 digest.
+    Hash,
+    32
+);
+
+/// The transactions root of a block without transactions.
+pub const EMPTY_ROOT: Hash = Hash([0; 32]);
+
+// Leaves and inner nodes are hashed with different prefixes so that an inner
+// node can never be passed off as a transaction.
+fn hash_leaf(tx_id: &TransactionId) -> Hash {
+    let digest: [u8; 32] = Sha256::new()
+        .chain_update([0u8])
+        .chain_update(tx_id)
+        .finalize()
+        .into();
+    Hash(digest)
+}
+
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let digest: [u8; 32] = Sha256::new()
+        .chain_update([1u8])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into();
+    Hash(digest)
+}
+
+// The root of the tree is hashed with the number of leaves: the promoted
+// nodes make the trees of several widths share their paths, e.g. the third
+// leaf of 3 and the fifth of 5, so a proof could otherwise claim either
+// position.
+fn hash_root(tx_count: usize, tree_root: &Hash) -> Hash {
+    let digest: [u8; 32] = Sha256::new()
+        .chain_update([2u8])
+        .chain_update((tx_count as u64).to_le_bytes())
+        .chain_update(tree_root)
+        .finalize()
+        .into();
+    Hash(digest)
+}
+
+/// Hash the pairs of the level, the last node is promoted as is when it has no pair
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+///
+/// Root of the Merkle tree over the `tx_id`s of the transactions
+///
+/// It is the commitment stored in [`BlockHeader::transactions_root`], and
+/// also commits to the number of transactions.
+pub fn merkle_root(transactions: &[Transaction]) -> Hash {
+    if transactions.is_empty() {
+        return EMPTY_ROOT;
+    }
+    let mut level: Vec<Hash> = transactions.iter().map(|tx| hash_leaf(&tx.tx_id)).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    hash_root(transactions.len(), &level[0])
+}
+
+/// The proof that a transaction belongs to the block at `block_height`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MerkleProof {
+    pub block_height: u32,
+    pub tx_index: usize,
+    pub tx_id: TransactionId,
+    /// Number of transactions of the block, it tells which nodes have no pair.
+    pub tx_count: usize,
+    /// Pair of the node on the path from the leaf to the root, for each level where it has one.
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Build the proof for the transaction at `tx_index` of the block transactions
+    pub fn new(block_height: u32, transactions: &[Transaction], tx_index: usize) -> Option<Self> {
+        let tx_id = transactions.get(tx_index)?.tx_id;
+        let mut siblings = vec![];
+        let mut level: Vec<Hash> = transactions.iter().map(|tx| hash_leaf(&tx.tx_id)).collect();
+        let mut index = tx_index;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(index ^ 1) {
+                siblings.push(*sibling);
+            }
+            level = next_level(&level);
+            index /= 2;
+        }
+        Some(MerkleProof {
+            block_height,
+            tx_index,
+            tx_id,
+            tx_count: transactions.len(),
+            siblings,
+        })
+    }
+
+    /// The root the proof leads to
+    ///
+    /// The side of each sibling follows from `tx_index` and `tx_count`, and
+    /// the root commits to `tx_count`, so neither can be rewritten.
+    fn root(&self) -> Option<Hash> {
+        if self.tx_index >= self.tx_count {
+            return None;
+        }
+        let mut node = hash_leaf(&self.tx_id);
+        let mut siblings = self.siblings.iter();
+        let (mut index, mut width) = (self.tx_index, self.tx_count);
+        while width > 1 {
+            if index ^ 1 < width {
+                let sibling = siblings.next()?;
+                node = if index % 2 == 0 {
+                    hash_node(&node, sibling)
+                } else {
+                    hash_node(sibling, &node)
+                };
+            }
+            index /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings
+            .next()
+            .is_none()
+            .then(|| hash_root(self.tx_count, &node))
+    }
+}
+
+/// The error returned when an inclusion proof cannot be built.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ProofError {
+    Server(ServerError),
+    /// The block has no transaction at this index.
+    MissingTransaction {
+        block_height: u32,
+        tx_index: usize,
+    },
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProofError::Server(e) => e.fmt(f),
+            ProofError::MissingTransaction {
+                block_height,
+                tx_index,
+            } => write!(
+                f,
+                "Proof error: no transaction {} in block {}",
+                tx_index, block_height
+            ),
+        }
+    }
+}
+
+impl From<ServerError> for ProofError {
+    fn from(e: ServerError) -> Self {
+        ProofError::Server(e)
+    }
+}
+
+/// Fetch the transactions of the block at `block_height` and prove the inclusion of the one at `tx_index`
+pub async fn prove_inclusion<S: ServerAPI + ?Sized>(
+    server: &S,
+    block_height: u32,
+    tx_index: usize,
+) -> Result<MerkleProof, ProofError> {
+    let transactions = server
+        .block_transactions(block_height..block_height + 1)
+        .await?
+        .pop()
+        .ok_or(ServerError::MissingHeight(block_height))?;
+    MerkleProof::new(block_height, &transactions, tx_index).ok_or(ProofError::MissingTransaction {
+        block_height,
+        tx_index,
+    })
+}
+
+/// Check that the proven transaction is committed to by the header
+pub fn verify_inclusion(proof: &MerkleProof, header: &BlockHeader) -> bool {
+    proof.block_height == header.block_height && proof.root() == Some(header.transactions_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::BlockStore;
+
+    fn transactions(count: u8) -> Vec<Transaction> {
+        (0..count)
+            .map(|i| Transaction {
+                tx_id: TransactionId([i; 32]),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn root_commits_to_every_transaction() {
+        assert_eq!(merkle_root(&[]), EMPTY_ROOT);
+        let txs = transactions(5);
+        assert_eq!(
+            merkle_root(&txs[..1]),
+            hash_root(1, &hash_leaf(&txs[0].tx_id))
+        );
+        let root = merkle_root(&txs);
+        let mut reordered = txs.clone();
+        reordered.swap(1, 2);
+        assert_ne!(merkle_root(&reordered), root);
+        assert_ne!(merkle_root(&txs[..4]), root);
+    }
+
+    #[test]
+    fn proves_every_transaction_of_any_tree_shape() {
+        for count in 1..12 {
+            let txs = transactions(count);
+            let header = BlockHeader {
+                block_height: 3,
+                transactions_root: merkle_root(&txs),
+                ..Default::default()
+            };
+            for index in 0..txs.len() {
+                let proof = MerkleProof::new(3, &txs, index).unwrap();
+                assert!(verify_inclusion(&proof, &header), "{} of {}", index, count);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_altered_proofs() {
+        let txs = transactions(6);
+        let header = BlockHeader {
+            block_height: 3,
+            transactions_root: merkle_root(&txs),
+            ..Default::default()
+        };
+        let proof = MerkleProof::new(3, &txs, 4).unwrap();
+
+        let mut other_tx = proof.clone();
+        other_tx.tx_id = TransactionId([9; 32]);
+        let mut other_index = proof.clone();
+        other_index.tx_index = 5;
+        let mut other_height = proof.clone();
+        other_height.block_height = 4;
+        let mut extra_sibling = proof.clone();
+        extra_sibling.siblings.push(Hash([0; 32]));
+        for altered in [other_tx, other_index, other_height, extra_sibling] {
+            assert!(!verify_inclusion(&altered, &header));
+        }
+        assert!(MerkleProof::new(3, &txs, 6).is_none());
+    }
+
+    #[test]
+    fn rejects_a_rewritten_position() {
+        // The third leaf of 3 and the fifth of 5 are both promoted up to the
+        // last level, where they are hashed with the same sibling
+        let txs = transactions(3);
+        let header = BlockHeader {
+            transactions_root: merkle_root(&txs),
+            ..Default::default()
+        };
+        let proof = MerkleProof::new(0, &txs, 2).unwrap();
+        assert!(verify_inclusion(&proof, &header));
+        let mut wider = proof.clone();
+        wider.tx_index = 4;
+        wider.tx_count = 5;
+        let mut other_count = proof.clone();
+        other_count.tx_count = 4;
+        let mut other_index = proof;
+        other_index.tx_index = 0;
+        for altered in [wider, other_count, other_index] {
+            assert!(!verify_inclusion(&altered, &header));
+        }
+    }
+
+    #[tokio::test]
+    async fn proves_inclusion_from_a_server() {
+        let txs = transactions(3);
+        let header = BlockHeader {
+            block_height: 0,
+            transactions_root: merkle_root(&txs),
+            ..Default::default()
+        };
+        let store: BlockStore = [Block {
+            header,
+            transactions: txs,
+        }]
+        .into_iter()
+        .collect();
+
+        let proof = prove_inclusion(&store, 0, 2).await.unwrap();
+        assert!(verify_inclusion(&proof, &header));
+        assert_eq!(
+            prove_inclusion(&store, 0, 3).await,
+            Err(ProofError::MissingTransaction {
+                block_height: 0,
+                tx_index: 3
+            })
+        );
+        assert_eq!(
+            prove_inclusion(&store, 1, 0).await,
+            Err(ProofError::Server(ServerError::MissingHeight(1)))
+        );
+    }
+}
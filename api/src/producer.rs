@@ -0,0 +1,195 @@
+use crate::mempool::Mempool;
+use crate::merkle::merkle_root;
+use crate::server::*;
+use crate::store::BlockStore;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often and how [`BlockProducer`] seals blocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProducerConfig {
+    pub interval: Duration,
+    /// Largest number of transactions of a block.
+    pub max_transactions: usize,
+    /// Difficulty the sealed headers are mined at.
+    pub difficulty: u32,
+}
+
+impl Default for ProducerConfig {
+    fn default() -> Self {
+        ProducerConfig {
+            interval: Duration::from_secs(1),
+            max_transactions: 100,
+            difficulty: 0,
+        }
+    }
+}
+
+///
+/// Seals the pending transactions of the mempool into blocks on the tip of the chain
+///
+/// The chain and the mempool are shared with the rest of the node, e.g. the
+/// warp handlers reading the chain and accepting transactions. Blocks are
+/// only added to the chain under the mempool lock.
+#[derive(Debug)]
+pub struct BlockProducer {
+    chain: BlockStore,
+    mempool: Arc<Mutex<Mempool>>,
+    config: ProducerConfig,
+}
+
+impl BlockProducer {
+    /// The mempool must hold the world state at the tip of `chain`
+    pub fn new(chain: BlockStore, mempool: Arc<Mutex<Mempool>>, config: ProducerConfig) -> Self {
+        BlockProducer {
+            chain,
+            mempool,
+            config,
+        }
+    }
+
+    /// Seal the best transactions of the mempool into a block appended to the chain
+    ///
+    /// The header is mined on a blocking thread without holding the chain or
+    /// the mempool. Returns `None` when no pending transaction can be included,
+    /// or when the tip moved while mining.
+    pub async fn produce(&self) -> Option<Block> {
+        let mut block = self.candidate()?;
+        block.header = tokio::task::spawn_blocking(move || {
+            block.header.mine();
+            block.header
+        })
+        .await
+        .ok()?;
+        self.seal(block)
+    }
+
+    /// Unmined block of the best transactions of the mempool on the tip of the chain
+    fn candidate(&self) -> Option<Block> {
+        let mempool = self.mempool.lock().unwrap();
+        let transactions = mempool.select(self.config.max_transactions);
+        if transactions.is_empty() {
+            return None;
+        }
+        let (block_height, parent_hash) = match self.chain.tip() {
+            Some(tip) => (tip.block_height + 1, tip.hash()),
+            None => (0, BlockHash::default()),
+        };
+        let header = BlockHeader {
+            block_height,
+            parent_hash,
+            transactions_root: merkle_root(&transactions),
+            consensus_fields: ConsensusFields {
+                difficulty: self.config.difficulty,
+                nonce: 0,
+            },
+        };
+        Some(Block {
+            header,
+            transactions,
+        })
+    }
+
+    /// Append a mined block if it still extends the tip and executes on the mempool state
+    fn seal(&self, block: Block) -> Option<Block> {
+        let mut mempool = self.mempool.lock().unwrap();
+        let tip_hash = self.chain.tip().map(|tip| tip.hash());
+        if tip_hash.unwrap_or_default() != block.header.parent_hash {
+            return None;
+        }
+        mempool.apply_block(&block).ok()?;
+        self.chain.insert(block.clone());
+        Some(block)
+    }
+
+    /// Produce a block every `interval`, calling `sealed` with each of them
+    pub async fn run(self, mut sealed: impl FnMut(&Block)) {
+        let mut interval = tokio::time::interval(self.config.interval);
+        loop {
+            interval.tick().await;
+            if let Some(block) = self.produce().await {
+                sealed(&block);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::signing_key;
+    use crate::state::WorldState;
+
+    fn transfer(nonce: u64, fee: u64) -> Transaction {
+        let fields = TransactionFields {
+            amount: 10,
+            fee,
+            nonce,
+            ..Default::default()
+        };
+        Transaction::sign(fields, &signing_key(0, 1))
+    }
+
+    fn producer(max_transactions: usize) -> (BlockProducer, BlockStore) {
+        let chain: BlockStore = [Block::default()].into_iter().collect();
+        let sender = Account(signing_key(0, 1).verifying_key().to_bytes());
+        let state: WorldState = [(sender, 100)].into_iter().collect();
+        let mempool = Arc::new(Mutex::new(Mempool::new(state, 100)));
+        for nonce in 0..3 {
+            mempool.lock().unwrap().submit(transfer(nonce, 1)).unwrap();
+        }
+        let config = ProducerConfig {
+            max_transactions,
+            difficulty: 2,
+            ..Default::default()
+        };
+        (BlockProducer::new(chain.clone(), mempool, config), chain)
+    }
+
+    #[tokio::test]
+    async fn seals_blocks_on_the_tip() {
+        let (producer, chain) = producer(2);
+        let first = producer.produce().await.unwrap();
+        let second = producer.produce().await.unwrap();
+        assert!(producer.produce().await.is_none());
+
+        assert_eq!(first.header.block_height, 1);
+        assert_eq!(first.header.parent_hash, Block::default().header.hash());
+        assert_eq!(first.transactions, vec![transfer(0, 1), transfer(1, 1)]);
+        assert_eq!(second.header.parent_hash, first.header.hash());
+        assert_eq!(second.transactions, vec![transfer(2, 1)]);
+        assert!(second.header.verify());
+        assert_eq!(chain.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn discards_blocks_mined_on_a_stale_tip() {
+        let (producer, chain) = producer(2);
+        let mut stale = producer.candidate().unwrap();
+        stale.header.mine();
+        let block = producer.produce().await.unwrap();
+
+        assert_eq!(producer.seal(stale), None);
+        assert_eq!(chain.tip(), Some(block.header));
+        assert_eq!(producer.mempool.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn produces_periodically() {
+        let (producer, chain) = producer(1);
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let config = ProducerConfig {
+            interval: Duration::from_millis(5),
+            ..producer.config
+        };
+        let producer = BlockProducer { config, ..producer };
+        let task = tokio::spawn(producer.run(move |block| {
+            sender.send(block.header.block_height).unwrap();
+        }));
+        for height in 1..4 {
+            assert_eq!(receiver.recv().await, Some(height));
+        }
+        task.abort();
+        assert_eq!(chain.len(), 4);
+    }
+}
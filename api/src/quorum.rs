@@ -0,0 +1,389 @@
+use crate::server::*;
+use async_trait::async_trait;
+use core::ops::Range;
+use std::future::Future;
+use std::sync::Mutex;
+
+/// When [`QuorumServerApi`] accepts an answer and bans a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuorumConfig {
+    /// Number of sources that have to give the same answer. An answer tied
+    /// with another one is never accepted, so a quorum of at most half of
+    /// the sources cannot be won by as many liars as honest sources.
+    pub quorum: usize,
+    /// A source is banned once it disagreed this many times with an accepted answer.
+    pub max_disagreements: u32,
+    /// A source is banned once this many calls failed in a row.
+    pub max_consecutive_failures: u32,
+}
+
+impl Default for QuorumConfig {
+    fn default() -> Self {
+        QuorumConfig {
+            quorum: 2,
+            max_disagreements: 1,
+            max_consecutive_failures: 3,
+        }
+    }
+}
+
+/// The record of a source, counted in calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceStats {
+    /// Answers that were accepted.
+    pub agreed: u64,
+    /// Answers that differed from the accepted one.
+    pub disagreed: u64,
+    /// Calls that failed without an answer, e.g. [`ServerError::Unavailable`].
+    pub failed: u64,
+    pub consecutive_failures: u32,
+    pub banned: bool,
+}
+
+/// The answer of a source, compared by its key.
+enum Vote<K> {
+    Answer(K),
+    /// An error the server answers on purpose, e.g. a missing height.
+    Error(ServerError),
+    Failure,
+}
+
+impl<K: PartialEq> PartialEq for Vote<K> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Vote::Answer(a), Vote::Answer(b)) => a == b,
+            (Vote::Error(a), Vote::Error(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+///
+/// `ServerAPI` aggregator cross-checking the answers of several sources
+///
+/// Every call is sent to all the sources that are not banned, and an answer
+/// is only returned when at least [`QuorumConfig::quorum`] of them gave the
+/// same one. Headers are compared by hash and transactions in full, since
+/// the Merkle root only covers their ids and not their fields or signature.
+/// The sources that disagree with an accepted answer, or keep failing, are
+/// banned and no longer called.
+#[derive(Debug)]
+pub struct QuorumServerApi<S> {
+    sources: Vec<S>,
+    config: QuorumConfig,
+    stats: Mutex<Vec<SourceStats>>,
+}
+
+impl<S: ServerAPI> QuorumServerApi<S> {
+    pub fn new(sources: Vec<S>, config: QuorumConfig) -> Self {
+        let stats = Mutex::new(vec![SourceStats::default(); sources.len()]);
+        QuorumServerApi {
+            sources,
+            config,
+            stats,
+        }
+    }
+
+    /// The record of each source, in the order they were given
+    pub fn stats(&self) -> Vec<SourceStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// The sources that are not banned, with their index
+    fn live(&self) -> Vec<(usize, &S)> {
+        let stats = self.stats.lock().unwrap();
+        self.sources
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !stats[*index].banned)
+            .collect()
+    }
+
+    /// Await the answers of `calls` and return the one given by a quorum of sources
+    ///
+    /// Answers are compared with `key`. The sources are scored once an
+    /// answer is accepted; without a quorum, only the failures are counted
+    /// since the liars cannot be told apart.
+    async fn vote<T, K, F>(
+        &self,
+        calls: Vec<(usize, F)>,
+        key: impl Fn(&T) -> K,
+    ) -> Result<T, ServerError>
+    where
+        K: PartialEq,
+        F: Future<Output = Result<T, ServerError>>,
+    {
+        let (indexes, calls): (Vec<_>, Vec<_>) = calls.into_iter().unzip();
+        let results = futures::future::join_all(calls).await;
+        let votes: Vec<Vote<K>> = results
+            .iter()
+            .map(|result| match result {
+                Ok(value) => Vote::Answer(key(value)),
+                Err(
+                    e @ (ServerError::MissingHeight(_)
+                    | ServerError::InvalidRange { .. }
+                    | ServerError::UnknownBlock(_)
+                    | ServerError::UnknownTransaction(_)),
+                ) => Vote::Error(e.clone()),
+                Err(_) => Vote::Failure,
+            })
+            .collect();
+
+        // The vote with the most sources, none on a tie between different votes
+        let mut best: Option<(usize, usize)> = None;
+        let mut tied = false;
+        for (i, vote) in votes.iter().enumerate() {
+            if let Vote::Failure = vote {
+                continue;
+            }
+            let agreeing = votes.iter().filter(|other| *other == vote).count();
+            match best {
+                Some((_, count)) if agreeing < count => {}
+                Some((winner, count)) if agreeing == count => tied |= votes[winner] != *vote,
+                _ => (best, tied) = (Some((i, agreeing)), false),
+            }
+        }
+        let accepted = best.filter(|&(_, agreeing)| !tied && agreeing >= self.config.quorum);
+
+        let mut stats = self.stats.lock().unwrap();
+        for (vote, &index) in votes.iter().zip(&indexes) {
+            let source = &mut stats[index];
+            if let Vote::Failure = vote {
+                source.failed += 1;
+                source.consecutive_failures += 1;
+                source.banned |=
+                    source.consecutive_failures >= self.config.max_consecutive_failures;
+                continue;
+            }
+            source.consecutive_failures = 0;
+            if let Some((winner, _)) = accepted {
+                if *vote == votes[winner] {
+                    source.agreed += 1;
+                } else {
+                    source.disagreed += 1;
+                    source.banned |= source.disagreed >= self.config.max_disagreements as u64;
+                }
+            }
+        }
+        match accepted {
+            Some((winner, _)) => results.into_iter().nth(winner).unwrap(),
+            None => Err(ServerError::NoQuorum {
+                agreeing: best.map_or(0, |(_, count)| count) as u32,
+                required: self.config.quorum as u32,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: ServerAPI + Send + Sync> ServerAPI for QuorumServerApi<S> {
+    async fn block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<BlockHeader>, ServerError> {
+        let calls = self
+            .live()
+            .into_iter()
+            .map(|(index, source)| (index, source.block_headers(block_height_range.clone())));
+        self.vote(calls.collect(), |headers| {
+            headers.iter().map(BlockHeader::hash).collect::<Vec<_>>()
+        })
+        .await
+    }
+
+    async fn block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<Vec<Transaction>>, ServerError> {
+        let calls = self
+            .live()
+            .into_iter()
+            .map(|(index, source)| (index, source.block_transactions(block_height_range.clone())));
+        self.vote(calls.collect(), Vec::clone).await
+    }
+
+    async fn latest_height(&self) -> Result<u32, ServerError> {
+        let calls = self.live().into_iter();
+        let calls = calls.map(|(index, source)| (index, source.latest_height()));
+        self.vote(calls.collect(), |height| *height).await
+    }
+
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+        let calls = self.live().into_iter();
+        let calls = calls.map(|(index, source)| (index, source.header_by_hash(hash)));
+        self.vote(calls.collect(), BlockHeader::hash).await
+    }
+
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+        let calls = self.live().into_iter();
+        let calls = calls.map(|(index, source)| (index, source.block_by_hash(hash)));
+        self.vote(calls.collect(), |block| {
+            (block.header.hash(), block.transactions.clone())
+        })
+        .await
+    }
+
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError> {
+        let calls = self.live().into_iter();
+        let calls = calls.map(|(index, source)| (index, source.transaction_by_id(tx_id)));
+        self.vote(calls.collect(), |location| *location).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{ChainGenerator, GeneratorConfig};
+
+    fn chain() -> Vec<Block> {
+        ChainGenerator::new(GeneratorConfig::default())
+            .take(10)
+            .collect()
+    }
+
+    fn list(blocks: &[Block]) -> BlockList {
+        blocks.iter().cloned().collect()
+    }
+
+    #[tokio::test]
+    async fn accepts_the_answer_of_the_quorum_and_bans_the_liar() {
+        let blocks = chain();
+        let mut forged = blocks.clone();
+        forged[4].header.consensus_fields.nonce += 1;
+        forged[6].transactions.pop();
+        let quorum = QuorumServerApi::new(
+            vec![list(&blocks), list(&forged), list(&blocks)],
+            QuorumConfig::default(),
+        );
+
+        let headers = quorum.block_headers(0..10).await.unwrap();
+        assert_eq!(headers, list(&blocks).block_headers(0..10).await.unwrap());
+        let stats = quorum.stats();
+        assert_eq!((stats[0].agreed, stats[2].agreed), (1, 1));
+        assert_eq!(stats[1].disagreed, 1);
+        assert!(stats[1].banned && !stats[0].banned);
+
+        // The liar is no longer asked, the two honest sources still agree
+        let transactions = quorum.block_transactions(6..7).await.unwrap();
+        assert_eq!(transactions[0], blocks[6].transactions);
+        assert_eq!(quorum.stats()[1].disagreed, 1);
+        assert_eq!(quorum.latest_height().await, Ok(9));
+    }
+
+    #[tokio::test]
+    async fn compares_the_transactions_beyond_their_ids() {
+        let blocks = chain();
+        let mut forged = blocks.clone();
+        // Same ids, so the same Merkle roots
+        forged[6].transactions[0].transaction_fields.amount += 1;
+        forged[7].transactions[0].signature = forged[7].transactions[1].signature;
+        let quorum = QuorumServerApi::new(
+            vec![list(&forged), list(&blocks), list(&blocks)],
+            QuorumConfig {
+                max_disagreements: 2,
+                ..Default::default()
+            },
+        );
+
+        let transactions = quorum.block_transactions(6..7).await.unwrap();
+        assert_eq!(transactions[0], blocks[6].transactions);
+        let hash = blocks[7].header.hash();
+        assert_eq!(quorum.block_by_hash(hash).await, Ok(blocks[7].clone()));
+        let stats = quorum.stats();
+        assert_eq!((stats[0].disagreed, stats[0].banned), (2, true));
+        assert_eq!((stats[1].agreed, stats[2].agreed), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn fails_without_a_quorum() {
+        let blocks = chain();
+        let mut first = blocks.clone();
+        first[2].transactions.clear();
+        let mut second = blocks.clone();
+        second[3].transactions.clear();
+        let quorum = QuorumServerApi::new(
+            vec![list(&first), list(&blocks), list(&second)],
+            QuorumConfig::default(),
+        );
+        assert_eq!(
+            quorum.block_transactions(0..5).await,
+            Err(ServerError::NoQuorum {
+                agreeing: 1,
+                required: 2
+            })
+        );
+        // Nobody can be blamed
+        assert!(quorum.stats().iter().all(|s| s.disagreed == 0 && !s.banned));
+        // The headers still agree
+        assert_eq!(quorum.block_headers(0..5).await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn refuses_answers_tied_with_another_one() {
+        let blocks = chain();
+        let mut forged = blocks.clone();
+        forged[4].header.consensus_fields.nonce += 1;
+        let quorum = QuorumServerApi::new(
+            vec![list(&forged), list(&forged), list(&blocks), list(&blocks)],
+            QuorumConfig::default(),
+        );
+        assert_eq!(
+            quorum.block_headers(0..10).await,
+            Err(ServerError::NoQuorum {
+                agreeing: 2,
+                required: 2
+            })
+        );
+        assert!(quorum.stats().iter().all(|s| s.disagreed == 0 && !s.banned));
+        // The answers that do not differ are still accepted
+        assert_eq!(quorum.block_headers(0..4).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn counts_errors_as_answers_and_bans_failing_sources() {
+        let blocks = chain();
+        let mut down = MockServerAPI::new();
+        down.expect_block_by_hash()
+            .times(2)
+            .returning(|_| Err(ServerError::Unavailable));
+        let mut up = MockServerAPI::new();
+        let block = blocks[3].clone();
+        up.expect_block_by_hash()
+            .returning(move |_| Ok(block.clone()));
+        let mut missing = MockServerAPI::new();
+        missing
+            .expect_block_by_hash()
+            .returning(|hash| Err(ServerError::UnknownBlock(hash)));
+        let config = QuorumConfig {
+            max_consecutive_failures: 2,
+            ..Default::default()
+        };
+        let quorum = QuorumServerApi::new(vec![down, up, missing], config);
+
+        let hash = blocks[3].header.hash();
+        assert_eq!(
+            quorum.block_by_hash(hash).await,
+            Err(ServerError::NoQuorum {
+                agreeing: 1,
+                required: 2
+            })
+        );
+        assert_eq!(
+            quorum.block_by_hash(hash).await,
+            Err(ServerError::NoQuorum {
+                agreeing: 1,
+                required: 2
+            })
+        );
+        let stats = quorum.stats();
+        assert_eq!((stats[0].failed, stats[0].banned), (2, true));
+        // Only two sources are left, which have to agree
+        assert!(matches!(
+            quorum.block_by_hash(hash).await,
+            Err(ServerError::NoQuorum { .. })
+        ));
+    }
+}
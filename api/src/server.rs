@@ -1,67 +1,275 @@
+use crate::bytes::hex_bytes;
+use crate::codec;
+use crate::merkle::Hash;
 use async_trait::async_trait;
 use core::ops::Range;
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, StreamExt};
 use list::linked_list::*;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fmt;
+use std::ops::Deref;
 
 /// Fields required by the consensus to validate the block.
 ///
-/// For simplicity, it is a dummy structure. We don't need to
-// verify the block header validity.
+/// The header is valid when its hash starts with at least `difficulty`
+/// zero bits, so the default fields are always valid.
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
-pub struct ConsensusFields;
+pub struct ConsensusFields {
+    pub difficulty: u32,
+    pub nonce: u64,
+}
+
+hex_bytes!(
+    /// The identifier of an account, the Ed25519 public key of its owner.
+    Account,
+    32
+);
 
 /// Fields of the transaction that cause some state transition of the blockchain.
 ///
-/// For simplicity, it is a dummy structure. We don't need to
-// implement state transition.
+/// `amount` moves from the sender to the recipient, `fee` is burnt, and
+/// `nonce` must be the number of transactions the sender already sent.
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
-pub struct TransactionFields;
+pub struct TransactionFields {
+    pub sender: Account,
+    pub recipient: Account,
+    pub amount: u64,
+    pub fee: u64,
+    pub nonce: u64,
+}
+
+hex_bytes!(
+    /// The identifier of the transaction in the database and the
+    /// network.
+    TransactionId,
+    32
+);
 
-/// The identifier of the transaction in the database and the
-// network.
-pub type TransactionId = [u8; 32];
+/// The hash of a block header, see [`BlockHeader::hash`].
+pub type BlockHash = Hash;
 
 /// The header of the block that describes the final state of the blockchain at `block_height`.
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct BlockHeader {
     pub block_height: u32,
+    /// Hash of the header at `block_height - 1`, zero for the genesis block.
+    pub parent_hash: BlockHash,
+    /// Merkle root of the `tx_id`s of the block, see [`crate::merkle::merkle_root`].
+    pub transactions_root: Hash,
     pub consensus_fields: ConsensusFields,
 }
 
 impl BlockHeader {
     /// The function that verifies the block header validity.
     pub fn verify(&self) -> bool {
-        true
+        leading_zero_bits(&self.hash()) >= self.consensus_fields.difficulty
+    }
+
+    /// SHA-256 of the canonical encoding of the header, see [`crate::codec`]
+    pub fn hash(&self) -> BlockHash {
+        Hash(Sha256::digest(codec::canonical_bytes(self)).into())
+    }
+
+    /// The work needed to find a valid nonce, `2^difficulty` hashes on average
+    pub fn work(&self) -> u128 {
+        1u128
+            .checked_shl(self.consensus_fields.difficulty)
+            .unwrap_or(u128::MAX)
+    }
+
+    /// Increase the nonce until the header is valid for its difficulty
+    pub fn mine(&mut self) {
+        while !self.verify() {
+            self.consensus_fields.nonce = self.consensus_fields.nonce.wrapping_add(1);
+        }
+    }
+}
+
+fn leading_zero_bits(hash: &Hash) -> u32 {
+    let mut bits = 0;
+    for byte in &hash.0 {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// The reason why a block was rejected during verification or execution.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StateTransitionReason {
+    /// [`BlockHeader::verify`] returned `false`.
+    InvalidHeader,
+    /// The parent hash is not the hash of the header at the previous height.
+    ParentMismatch,
+    /// The header hash differs from the one of a trusted checkpoint.
+    CheckpointMismatch,
+    /// The transactions do not match the transactions root of the header.
+    TransactionsRootMismatch,
+    /// The transaction is not signed by its sender.
+    InvalidSignature,
+    /// The sender cannot pay for the transaction.
+    InsufficientBalance,
+    /// The transaction nonce does not follow the sender's last nonce.
+    BadNonce,
+}
+
+impl fmt::Display for StateTransitionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateTransitionReason::InvalidHeader => write!(f, "invalid header"),
+            StateTransitionReason::ParentMismatch => write!(f, "parent mismatch"),
+            StateTransitionReason::CheckpointMismatch => write!(f, "checkpoint mismatch"),
+            StateTransitionReason::TransactionsRootMismatch => {
+                write!(f, "transactions root mismatch")
+            }
+            StateTransitionReason::InvalidSignature => write!(f, "invalid signature"),
+            StateTransitionReason::InsufficientBalance => write!(f, "insufficient balance"),
+            StateTransitionReason::BadNonce => write!(f, "bad nonce"),
+        }
     }
 }
 
 /// The error that describe failed state transition.
-#[derive(Debug, Clone)]
-pub struct StateTransitionError;
+///
+/// `tx_index` and `tx_id` are only set when a transaction failed to
+/// execute, not when the header or the whole body was rejected.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StateTransitionError {
+    pub block_height: u32,
+    pub tx_index: Option<usize>,
+    pub tx_id: Option<TransactionId>,
+    pub reason: StateTransitionReason,
+}
+
+impl StateTransitionError {
+    /// The header at `block_height` failed verification.
+    pub fn invalid_header(block_height: u32) -> Self {
+        StateTransitionError {
+            block_height,
+            tx_index: None,
+            tx_id: None,
+            reason: StateTransitionReason::InvalidHeader,
+        }
+    }
+
+    /// The header at `block_height` does not follow the header at the previous height.
+    pub fn parent_mismatch(block_height: u32) -> Self {
+        StateTransitionError {
+            block_height,
+            tx_index: None,
+            tx_id: None,
+            reason: StateTransitionReason::ParentMismatch,
+        }
+    }
+
+    /// The header at `block_height` contradicts a trusted checkpoint.
+    pub fn checkpoint_mismatch(block_height: u32) -> Self {
+        StateTransitionError {
+            block_height,
+            tx_index: None,
+            tx_id: None,
+            reason: StateTransitionReason::CheckpointMismatch,
+        }
+    }
+
+    /// The transactions at `block_height` are not the ones committed to by the header.
+    pub fn transactions_root_mismatch(block_height: u32) -> Self {
+        StateTransitionError {
+            block_height,
+            tx_index: None,
+            tx_id: None,
+            reason: StateTransitionReason::TransactionsRootMismatch,
+        }
+    }
+
+    /// The transaction at `tx_index` of the block at `block_height` failed to execute.
+    pub fn transaction(
+        block_height: u32,
+        tx_index: usize,
+        tx_id: TransactionId,
+        reason: StateTransitionReason,
+    ) -> Self {
+        StateTransitionError {
+            block_height,
+            tx_index: Some(tx_index),
+            tx_id: Some(tx_id),
+            reason,
+        }
+    }
+}
 
 impl fmt::Display for StateTransitionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "State transition error")
+        write!(
+            f,
+            "State transition error at height {}: {}",
+            self.block_height, self.reason
+        )?;
+        if let (Some(index), Some(tx_id)) = (self.tx_index, self.tx_id) {
+            write!(f, " (transaction {} {})", index, tx_id)?;
+        }
+        Ok(())
     }
 }
 
+hex_bytes!(
+    /// The Ed25519 signature of a `tx_id` by the sender of the transaction.
+    Signature,
+    64
+);
+
 /// The transaction causes a state transition on the blockchain.
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct Transaction {
+    /// Hash of the fields, see [`Transaction::id`].
     pub tx_id: TransactionId,
     pub transaction_fields: TransactionFields,
+    /// Signature of the `tx_id` by the sender.
+    pub signature: Signature,
 }
 
 impl Transaction {
+    /// The `tx_id` of a transaction, SHA-256 of the canonical encoding of its fields
+    pub fn id(fields: &TransactionFields) -> TransactionId {
+        TransactionId(Sha256::digest(codec::canonical_bytes(fields)).into())
+    }
+
+    /// The transaction sent by the owner of `key`, whose account replaces `fields.sender`
+    pub fn sign(mut fields: TransactionFields, key: &SigningKey) -> Self {
+        fields.sender = Account(key.verifying_key().to_bytes());
+        let tx_id = Transaction::id(&fields);
+        Transaction {
+            tx_id,
+            transaction_fields: fields,
+            signature: Signature(key.sign(tx_id.as_ref()).to_bytes()),
+        }
+    }
+
     /// The function executes transaction and performance state
     // transition.
-    pub fn execute(self) -> Result<(), StateTransitionError> {
-        let a = 0;
-        if a == 0 {
-            return Ok(());
+    ///
+    /// Only the checks that need no world state are done here: the
+    /// `tx_id` must be the hash of the fields, signed by the sender.
+    /// Balances and nonces are checked by
+    /// [`crate::state::WorldState::apply_transaction`].
+    ///
+    /// The caller knows the block height and the position of the
+    /// transaction, so only the reason of the failure is returned.
+    pub fn execute(self) -> Result<(), StateTransitionReason> {
+        let fields = &self.transaction_fields;
+        let signature = ed25519_dalek::Signature::from_bytes(&self.signature.0);
+        let signed = self.tx_id == Transaction::id(fields)
+            && VerifyingKey::from_bytes(&fields.sender.0)
+                .is_ok_and(|sender| sender.verify(self.tx_id.as_ref(), &signature).is_ok());
+        if !signed {
+            return Err(StateTransitionReason::InvalidSignature);
         }
-        return Err(StateTransitionError);
+        Ok(())
     }
 }
 
@@ -72,16 +280,76 @@ pub struct Block {
     pub transactions: Vec<Transaction>,
 }
 
+/// Where a transaction is stored in the chain.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransactionLocation {
+    pub block_height: u32,
+    /// Position of the transaction in the block.
+    pub tx_index: usize,
+}
+
 /// The error that describes failure on the server side.
-#[derive(Debug, Serialize)]
-pub struct ServerError;
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ServerError {
+    /// No block is stored at the requested height.
+    MissingHeight(u32),
+    /// The range ends before it starts.
+    InvalidRange { start: u32, end: u32 },
+    /// The range is longer than the server accepts in one call.
+    RangeTooLarge { requested: u32, max: u32 },
+    /// The client exceeded its request quota and may retry after the given delay.
+    Throttled { retry_after_ms: u64 },
+    /// The server is up but failed to answer the request.
+    Unavailable,
+    /// The server could not be reached or its answer could not be read.
+    Transport(String),
+    /// No block with this hash is stored.
+    UnknownBlock(BlockHash),
+    /// No stored block contains a transaction with this id.
+    UnknownTransaction(TransactionId),
+    /// Fewer sources than required gave the same answer.
+    NoQuorum { agreeing: u32, required: u32 },
+}
 
 impl fmt::Display for ServerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Server error")
+        match self {
+            ServerError::MissingHeight(height) => {
+                write!(f, "Server error: no block at height {}", height)
+            }
+            ServerError::InvalidRange { start, end } => {
+                write!(f, "Server error: invalid range {}..{}", start, end)
+            }
+            ServerError::RangeTooLarge { requested, max } => write!(
+                f,
+                "Server error: range of {} heights is larger than {}",
+                requested, max
+            ),
+            ServerError::Throttled { retry_after_ms } => {
+                write!(
+                    f,
+                    "Server error: throttled, retry after {} ms",
+                    retry_after_ms
+                )
+            }
+            ServerError::Unavailable => write!(f, "Server error: unavailable"),
+            ServerError::Transport(e) => write!(f, "Server error: transport failure: {}", e),
+            ServerError::UnknownBlock(hash) => write!(f, "Server error: unknown block {}", hash),
+            ServerError::UnknownTransaction(tx_id) => {
+                write!(f, "Server error: unknown transaction {}", tx_id)
+            }
+            ServerError::NoQuorum { agreeing, required } => write!(
+                f,
+                "Server error: {} sources agree, {} required",
+                agreeing, required
+            ),
+        }
     }
 }
 
+/// Number of heights fetched at once by the streams of [`ServerAPI`].
+pub const STREAM_CHUNK_LEN: u32 = 100;
+
 /// The API is supported by the server.
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
@@ -107,6 +375,153 @@ pub trait ServerAPI {
         &self,
         block_height_range: Range<u32>,
     ) -> Result<Vec<Vec<Transaction>>, ServerError>;
+
+    /// Return the height of the last block of the chain.
+    ///
+    /// Fails with [`ServerError::MissingHeight`] of `0` when the chain is empty.
+    async fn latest_height(&self) -> Result<u32, ServerError>;
+
+    /// Return the header of the block with the given hash.
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError>;
+
+    /// Return the block with the given hash.
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError>;
+
+    /// Return the height of the block containing the transaction and its
+    // index in the block.
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError>;
+
+    /// Stream the headers of `block_height_range` in order, see
+    // [`chunked_block_headers`].
+    ///
+    /// The stream ends after the first error.
+    fn stream_block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> BoxStream<'_, Result<BlockHeader, ServerError>>
+    where
+        Self: Sync,
+    {
+        chunked_block_headers(self, block_height_range)
+    }
+
+    /// Stream the transactions of each block of `block_height_range` in
+    // order, see [`chunked_block_transactions`].
+    ///
+    /// The stream ends after the first error.
+    fn stream_block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> BoxStream<'_, Result<Vec<Transaction>, ServerError>>
+    where
+        Self: Sync,
+    {
+        chunked_block_transactions(self, block_height_range)
+    }
+}
+
+/// Fetches the items of a range of heights from the server.
+pub(crate) type Fetch<S, T> =
+    for<'s> fn(&'s S, Range<u32>) -> BoxFuture<'s, Result<Vec<T>, ServerError>>;
+
+/// Stream the headers of the range with `block_headers`, [`STREAM_CHUNK_LEN`] heights at a time
+///
+/// A chunk is only requested once the previous one is consumed, so a slow
+/// consumer slows down the requests instead of buffering the whole range.
+/// `server` is a reference or a smart pointer, e.g. an `Arc` for a
+/// `'static` stream.
+pub fn chunked_block_headers<'a, P, S>(
+    server: P,
+    block_height_range: Range<u32>,
+) -> BoxStream<'a, Result<BlockHeader, ServerError>>
+where
+    P: Deref<Target = S> + Send + 'a,
+    S: ServerAPI + Sync + ?Sized + 'a,
+{
+    let fetch: Fetch<S, BlockHeader> = |server, range| server.block_headers(range);
+    chunked(server, block_height_range, fetch)
+}
+
+/// Stream the transactions of the range with `block_transactions`, see [`chunked_block_headers`]
+pub fn chunked_block_transactions<'a, P, S>(
+    server: P,
+    block_height_range: Range<u32>,
+) -> BoxStream<'a, Result<Vec<Transaction>, ServerError>>
+where
+    P: Deref<Target = S> + Send + 'a,
+    S: ServerAPI + Sync + ?Sized + 'a,
+{
+    let fetch: Fetch<S, Vec<Transaction>> = |server, range| server.block_transactions(range);
+    chunked(server, block_height_range, fetch)
+}
+
+struct Chunks<P, S: ?Sized, T> {
+    server: P,
+    fetch: Fetch<S, T>,
+    next: u32,
+    end: u32,
+    buffer: std::vec::IntoIter<T>,
+    started: bool,
+    failed: bool,
+}
+
+fn chunked<'a, P, S, T>(
+    server: P,
+    block_height_range: Range<u32>,
+    fetch: Fetch<S, T>,
+) -> BoxStream<'a, Result<T, ServerError>>
+where
+    P: Deref<Target = S> + Send + 'a,
+    S: Sync + ?Sized + 'a,
+    T: Send + 'a,
+{
+    let chunks = Chunks {
+        server,
+        fetch,
+        next: block_height_range.start,
+        end: block_height_range.end,
+        buffer: vec![].into_iter(),
+        started: false,
+        failed: false,
+    };
+    futures::stream::unfold(chunks, |mut chunks| async move {
+        loop {
+            if let Some(item) = chunks.buffer.next() {
+                return Some((Ok(item), chunks));
+            }
+            // The first request is always made, so that the server reports
+            // an invalid range
+            if chunks.failed || (chunks.started && chunks.next >= chunks.end) {
+                return None;
+            }
+            chunks.started = true;
+            let end = if chunks.next < chunks.end {
+                chunks.end.min(chunks.next.saturating_add(STREAM_CHUNK_LEN))
+            } else {
+                chunks.end
+            };
+            let range = chunks.next..end;
+            match (chunks.fetch)(&chunks.server, range.clone()).await {
+                Ok(items) if items.len() == range.len() => {
+                    chunks.buffer = items.into_iter();
+                    chunks.next = end;
+                }
+                Ok(items) => {
+                    chunks.failed = true;
+                    let missing = range.start + items.len() as u32;
+                    return Some((Err(ServerError::MissingHeight(missing)), chunks));
+                }
+                Err(e) => {
+                    chunks.failed = true;
+                    return Some((Err(e), chunks));
+                }
+            }
+        }
+    })
+    .boxed()
 }
 
 ///
@@ -135,7 +550,7 @@ impl ServerAPI for BlockList {
                 return Ok(headers);
             }
             if block.clone().unwrap().header.block_height != i {
-                return Err(ServerError);
+                return Err(ServerError::MissingHeight(i));
             }
             if i < h_start {
                 continue;
@@ -159,7 +574,7 @@ impl ServerAPI for BlockList {
                 return Ok(transactions);
             }
             if block.clone().unwrap().header.block_height != i {
-                return Err(ServerError);
+                return Err(ServerError::MissingHeight(i));
             }
             if i < h_start {
                 continue;
@@ -168,4 +583,100 @@ impl ServerAPI for BlockList {
         }
         Ok(transactions)
     }
+
+    // `BlockList` is the plain linked list and keeps no index: the height of
+    // the tip is read from the tail, but the lookups by hash and by id walk
+    // the list from the tip. `BlockStore` is the indexed in-memory backend,
+    // see `BlockStore::from(&list)`.
+    async fn latest_height(&self) -> Result<u32, ServerError> {
+        self.iter()
+            .next_back()
+            .map(|block| block.header.block_height)
+            .ok_or(ServerError::MissingHeight(0))
+    }
+
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+        self.block_by_hash(hash).await.map(|block| block.header)
+    }
+
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+        self.iter()
+            .rev()
+            .find(|block| block.header.hash() == hash)
+            .ok_or(ServerError::UnknownBlock(hash))
+    }
+
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError> {
+        self.iter()
+            .rev()
+            .find_map(|block| {
+                let tx_index = block.transactions.iter().position(|tx| tx.tx_id == tx_id)?;
+                Some(TransactionLocation {
+                    block_height: block.header.block_height,
+                    tx_index,
+                })
+            })
+            .ok_or(ServerError::UnknownTransaction(tx_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::BlockStore;
+    use futures::TryStreamExt;
+    use mockall::predicate::eq;
+
+    fn block(height: u32) -> Block {
+        Block {
+            header: BlockHeader {
+                block_height: height,
+                ..Default::default()
+            },
+            transactions: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_the_range_in_chunks() {
+        let store: BlockStore = (0..250).map(block).collect();
+        let headers: Vec<BlockHeader> = store
+            .stream_block_headers(10..250)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(headers, store.block_headers(10..250).await.unwrap());
+
+        // The next chunk is only requested once the first one is consumed
+        let mut server = MockServerAPI::new();
+        server
+            .expect_block_transactions()
+            .with(eq(0..STREAM_CHUNK_LEN))
+            .times(1)
+            .returning(|range| Ok(vec![vec![]; range.len()]));
+        let mut stream = chunked_block_transactions(&server, 0..1000);
+        for _ in 0..STREAM_CHUNK_LEN {
+            assert_eq!(stream.next().await, Some(Ok(vec![])));
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_ends_after_an_error() {
+        let store: BlockStore = (0..250).filter(|&h| h != 150).map(block).collect();
+        let items: Vec<_> = store.stream_block_headers(0..250).collect().await;
+        assert_eq!(items.len(), 101);
+        assert_eq!(items[100], Err(ServerError::MissingHeight(150)));
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 5..4;
+        let items: Vec<_> = store.stream_block_transactions(reversed).collect().await;
+        assert_eq!(
+            items,
+            vec![Err(ServerError::InvalidRange { start: 5, end: 4 })]
+        );
+        assert_eq!(store.stream_block_headers(7..7).next().await, None);
+    }
 }
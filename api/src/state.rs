@@ -0,0 +1,207 @@
+use crate::server::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The balance and the number of transactions sent by an account.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountState {
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+/// What a block changed, to revert it with [`WorldState::undo_block`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BlockUndo {
+    /// Every account the block touched, as it was before the block.
+    previous: HashMap<Account, Option<AccountState>>,
+}
+
+///
+/// The accounts of the chain after the execution of its blocks
+///
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WorldState {
+    accounts: HashMap<Account, AccountState>,
+}
+
+impl WorldState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn account(&self, account: &Account) -> AccountState {
+        self.accounts.get(account).copied().unwrap_or_default()
+    }
+
+    pub fn balance(&self, account: &Account) -> u64 {
+        self.account(account).balance
+    }
+
+    pub fn nonce(&self, account: &Account) -> u64 {
+        self.account(account).nonce
+    }
+
+    /// Execute the transaction, moving its amount and burning its fee
+    pub fn apply_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<(), StateTransitionReason> {
+        transaction.execute()?;
+        let fields = &transaction.transaction_fields;
+        let mut sender = self.account(&fields.sender);
+        if fields.nonce != sender.nonce {
+            return Err(StateTransitionReason::BadNonce);
+        }
+        sender.balance = fields
+            .amount
+            .checked_add(fields.fee)
+            .and_then(|cost| sender.balance.checked_sub(cost))
+            .ok_or(StateTransitionReason::InsufficientBalance)?;
+        sender.nonce += 1;
+        self.accounts.insert(fields.sender, sender);
+        let recipient = self.accounts.entry(fields.recipient).or_default();
+        recipient.balance = recipient.balance.saturating_add(fields.amount);
+        Ok(())
+    }
+
+    /// Execute the transactions of the block in order
+    ///
+    /// The state is left unchanged when one of them fails.
+    pub fn apply_block(&mut self, block: &Block) -> Result<BlockUndo, StateTransitionError> {
+        let mut undo = BlockUndo::default();
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            let fields = &transaction.transaction_fields;
+            for account in [fields.sender, fields.recipient] {
+                undo.previous
+                    .entry(account)
+                    .or_insert_with(|| self.accounts.get(&account).copied());
+            }
+            if let Err(reason) = self.apply_transaction(transaction) {
+                self.undo_block(undo);
+                return Err(StateTransitionError::transaction(
+                    block.header.block_height,
+                    index,
+                    transaction.tx_id,
+                    reason,
+                ));
+            }
+        }
+        Ok(undo)
+    }
+
+    /// Revert the last applied block
+    pub fn undo_block(&mut self, undo: BlockUndo) {
+        for (account, previous) in undo.previous {
+            match previous {
+                Some(state) => self.accounts.insert(account, state),
+                None => self.accounts.remove(&account),
+            };
+        }
+    }
+}
+
+impl FromIterator<(Account, u64)> for WorldState {
+    /// The state with the given balances and no transaction sent yet
+    fn from_iter<I: IntoIterator<Item = (Account, u64)>>(balances: I) -> Self {
+        WorldState {
+            accounts: balances
+                .into_iter()
+                .map(|(account, balance)| (account, AccountState { balance, nonce: 0 }))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::signing_key;
+
+    fn account(index: usize) -> Account {
+        signing_key(0, index).verifying_key().to_bytes().into()
+    }
+
+    fn transfer(sender: usize, recipient: usize, amount: u64, nonce: u64) -> Transaction {
+        let fields = TransactionFields {
+            recipient: account(recipient),
+            amount,
+            fee: 1,
+            nonce,
+            ..Default::default()
+        };
+        Transaction::sign(fields, &signing_key(0, sender))
+    }
+
+    #[test]
+    fn applies_transfers() {
+        let mut state: WorldState = [(account(1), 100)].into_iter().collect();
+        state.apply_transaction(&transfer(1, 2, 40, 0)).unwrap();
+        assert_eq!(state.balance(&account(1)), 59);
+        assert_eq!(state.balance(&account(2)), 40);
+        assert_eq!(state.nonce(&account(1)), 1);
+
+        assert_eq!(
+            state.apply_transaction(&transfer(1, 2, 10, 0)),
+            Err(StateTransitionReason::BadNonce)
+        );
+        assert_eq!(
+            state.apply_transaction(&transfer(1, 2, 59, 1)),
+            Err(StateTransitionReason::InsufficientBalance)
+        );
+        assert_eq!(state.balance(&account(1)), 59);
+    }
+
+    #[test]
+    fn rejects_transactions_not_signed_by_the_sender() {
+        let mut state: WorldState = [(account(1), 100)].into_iter().collect();
+        let mut altered = transfer(1, 2, 10, 0);
+        altered.transaction_fields.amount = 20;
+        let mut forged = transfer(1, 2, 10, 0);
+        forged.transaction_fields.sender = account(3);
+        forged.tx_id = Transaction::id(&forged.transaction_fields);
+        for transaction in [altered, forged] {
+            assert_eq!(
+                state.apply_transaction(&transaction),
+                Err(StateTransitionReason::InvalidSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn failed_block_leaves_the_state_unchanged() {
+        let mut state: WorldState = [(account(1), 100)].into_iter().collect();
+        let before = state.clone();
+        let block = Block {
+            header: BlockHeader {
+                block_height: 4,
+                ..Default::default()
+            },
+            transactions: vec![transfer(1, 2, 10, 0), transfer(1, 2, 10, 2)],
+        };
+        let error = state.apply_block(&block).unwrap_err();
+        assert_eq!(
+            error,
+            StateTransitionError::transaction(
+                4,
+                1,
+                block.transactions[1].tx_id,
+                StateTransitionReason::BadNonce
+            )
+        );
+        assert_eq!(state, before);
+    }
+
+    #[test]
+    fn undo_reverts_the_block() {
+        let mut state: WorldState = [(account(1), 100)].into_iter().collect();
+        let before = state.clone();
+        let block = Block {
+            header: BlockHeader::default(),
+            transactions: vec![transfer(1, 2, 10, 0), transfer(2, 3, 5, 0)],
+        };
+        let undo = state.apply_block(&block).unwrap();
+        assert_eq!(state.balance(&account(3)), 5);
+        state.undo_block(undo);
+        assert_eq!(state, before);
+    }
+}
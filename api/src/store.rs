@@ -0,0 +1,307 @@
+use crate::blocks::{BlockSink, BuildError};
+use crate::server::*;
+use async_trait::async_trait;
+use core::ops::Range;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Default)]
+struct Indexes {
+    blocks: BTreeMap<u32, Block>,
+    /// Height of each block by its hash.
+    by_hash: HashMap<BlockHash, u32>,
+    /// Location of each transaction by its id, in the last inserted block
+    /// containing it.
+    by_tx: HashMap<TransactionId, TransactionLocation>,
+}
+
+impl Indexes {
+    fn insert(&mut self, block: Block) -> Option<Block> {
+        let block_height = block.header.block_height;
+        let hash = block.header.hash();
+        self.by_hash.insert(hash, block_height);
+        for (tx_index, tx) in block.transactions.iter().enumerate() {
+            let location = TransactionLocation {
+                block_height,
+                tx_index,
+            };
+            self.by_tx.insert(tx.tx_id, location);
+        }
+        let replaced = self.blocks.insert(block_height, block)?;
+        let block = &self.blocks[&block_height];
+        // Forget what only the replaced block indexed
+        let replaced_hash = replaced.header.hash();
+        if replaced_hash != hash {
+            self.by_hash.remove(&replaced_hash);
+        }
+        for tx in &replaced.transactions {
+            let stale = self
+                .by_tx
+                .get(&tx.tx_id)
+                .is_some_and(|location| location.block_height == block_height);
+            if stale && !block.transactions.iter().any(|t| t.tx_id == tx.tx_id) {
+                self.by_tx.remove(&tx.tx_id);
+            }
+        }
+        Some(replaced)
+    }
+}
+
+///
+/// Blocks indexed by their height, their hash and the ids of their transactions
+///
+/// Range queries only visit the requested heights instead of walking the
+/// chain from genesis like [`BlockList`]. Clones share the same storage, so
+/// the store can be handed to the server and still be filled afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct BlockStore {
+    indexes: Arc<RwLock<Indexes>>,
+}
+
+impl BlockStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store the block at its header height, returning the block it replaces
+    pub fn insert(&self, block: Block) -> Option<Block> {
+        self.indexes.write().unwrap().insert(block)
+    }
+
+    pub fn get(&self, height: u32) -> Option<Block> {
+        self.indexes.read().unwrap().blocks.get(&height).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.indexes.read().unwrap().blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indexes.read().unwrap().blocks.is_empty()
+    }
+
+    /// The header of the highest stored block
+    pub fn tip(&self) -> Option<BlockHeader> {
+        let indexes = self.indexes.read().unwrap();
+        indexes
+            .blocks
+            .last_key_value()
+            .map(|(_, block)| block.header)
+    }
+
+    /// The block with the given hash, with `f` applied under the read lock
+    fn by_hash<T>(&self, hash: BlockHash, f: impl Fn(&Block) -> T) -> Result<T, ServerError> {
+        let indexes = self.indexes.read().unwrap();
+        indexes
+            .by_hash
+            .get(&hash)
+            .and_then(|height| indexes.blocks.get(height))
+            .map(f)
+            .ok_or(ServerError::UnknownBlock(hash))
+    }
+
+    /// Collect `f(block)` for every height of the range
+    ///
+    /// Every height has to be stored: a gap is reported as
+    /// [`ServerError::MissingHeight`] instead of returning a shorter list.
+    fn collect_range<T>(
+        &self,
+        block_height_range: Range<u32>,
+        f: impl Fn(&Block) -> T,
+    ) -> Result<Vec<T>, ServerError> {
+        if block_height_range.start > block_height_range.end {
+            return Err(ServerError::InvalidRange {
+                start: block_height_range.start,
+                end: block_height_range.end,
+            });
+        }
+        let blocks = &self.indexes.read().unwrap().blocks;
+        let mut items = Vec::with_capacity(block_height_range.len().min(blocks.len()));
+        let mut stored = blocks.range(block_height_range.clone());
+        for height in block_height_range {
+            match stored.next() {
+                Some((&h, block)) if h == height => items.push(f(block)),
+                _ => return Err(ServerError::MissingHeight(height)),
+            }
+        }
+        Ok(items)
+    }
+}
+
+impl From<&BlockList> for BlockStore {
+    fn from(list: &BlockList) -> Self {
+        list.iter().collect()
+    }
+}
+
+impl FromIterator<Block> for BlockStore {
+    fn from_iter<I: IntoIterator<Item = Block>>(iter: I) -> Self {
+        let mut indexes = Indexes::default();
+        for block in iter {
+            indexes.insert(block);
+        }
+        BlockStore {
+            indexes: Arc::new(RwLock::new(indexes)),
+        }
+    }
+}
+
+impl BlockSink for BlockStore {
+    fn store(&mut self, block: Block) -> Result<(), BuildError> {
+        self.insert(block);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ServerAPI for BlockStore {
+    async fn block_headers(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<BlockHeader>, ServerError> {
+        self.collect_range(block_height_range, |block| block.header)
+    }
+
+    async fn block_transactions(
+        &self,
+        block_height_range: Range<u32>,
+    ) -> Result<Vec<Vec<Transaction>>, ServerError> {
+        self.collect_range(block_height_range, |block| block.transactions.clone())
+    }
+
+    async fn latest_height(&self) -> Result<u32, ServerError> {
+        let indexes = self.indexes.read().unwrap();
+        match indexes.blocks.last_key_value() {
+            Some((&height, _)) => Ok(height),
+            None => Err(ServerError::MissingHeight(0)),
+        }
+    }
+
+    async fn header_by_hash(&self, hash: BlockHash) -> Result<BlockHeader, ServerError> {
+        self.by_hash(hash, |block| block.header)
+    }
+
+    async fn block_by_hash(&self, hash: BlockHash) -> Result<Block, ServerError> {
+        self.by_hash(hash, Block::clone)
+    }
+
+    async fn transaction_by_id(
+        &self,
+        tx_id: TransactionId,
+    ) -> Result<TransactionLocation, ServerError> {
+        let indexes = self.indexes.read().unwrap();
+        match indexes.by_tx.get(&tx_id) {
+            Some(location) => Ok(*location),
+            None => Err(ServerError::UnknownTransaction(tx_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(height: u32) -> Block {
+        Block {
+            header: BlockHeader {
+                block_height: height,
+                ..Default::default()
+            },
+            transactions: vec![Transaction::default(); height as usize],
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_range_queries_by_height() {
+        let store: BlockStore = (0..10).map(block).collect();
+        let headers = store.block_headers(4..7).await.unwrap();
+        let heights: Vec<_> = headers.iter().map(|h| h.block_height).collect();
+        assert_eq!(heights, vec![4, 5, 6]);
+
+        let transactions = store.block_transactions(9..10).await.unwrap();
+        assert_eq!(transactions, vec![vec![Transaction::default(); 9]]);
+        assert_eq!(store.block_headers(3..3).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn reports_missing_heights() {
+        let store: BlockStore = [0, 1, 3].into_iter().map(block).collect();
+        assert_eq!(
+            store.block_headers(0..4).await,
+            Err(ServerError::MissingHeight(2))
+        );
+        assert_eq!(
+            store.block_transactions(3..5).await,
+            Err(ServerError::MissingHeight(4))
+        );
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 5..4;
+        assert_eq!(
+            store.block_headers(reversed).await,
+            Err(ServerError::InvalidRange { start: 5, end: 4 })
+        );
+
+        store.insert(block(2));
+        assert_eq!(store.block_headers(0..4).await.unwrap().len(), 4);
+        // The answer is not reserved for the whole range
+        assert_eq!(
+            store.block_headers(0..u32::MAX).await,
+            Err(ServerError::MissingHeight(4))
+        );
+    }
+
+    #[tokio::test]
+    async fn looks_up_blocks_and_transactions() {
+        let tx = |i: u8| Transaction {
+            tx_id: TransactionId([i; 32]),
+            ..Default::default()
+        };
+        let mut first = block(0);
+        first.transactions = vec![tx(1), tx(2)];
+        let store: BlockStore = [first.clone(), block(1)].into_iter().collect();
+        assert_eq!(store.latest_height().await, Ok(1));
+        let hash = first.header.hash();
+        assert_eq!(store.header_by_hash(hash).await, Ok(first.header));
+        assert_eq!(store.block_by_hash(hash).await, Ok(first.clone()));
+        assert_eq!(
+            store.transaction_by_id(tx(2).tx_id).await,
+            Ok(TransactionLocation {
+                block_height: 0,
+                tx_index: 1
+            })
+        );
+
+        // Replacing the block forgets its hash and its transactions
+        let mut replacement = first.clone();
+        replacement.header.consensus_fields.nonce = 1;
+        replacement.transactions = vec![tx(2)];
+        store.insert(replacement);
+        assert_eq!(
+            store.block_by_hash(hash).await,
+            Err(ServerError::UnknownBlock(hash))
+        );
+        assert_eq!(
+            store.transaction_by_id(tx(1).tx_id).await,
+            Err(ServerError::UnknownTransaction(tx(1).tx_id))
+        );
+        assert_eq!(
+            store.transaction_by_id(tx(2).tx_id).await.unwrap().tx_index,
+            0
+        );
+        assert_eq!(
+            BlockStore::new().latest_height().await,
+            Err(ServerError::MissingHeight(0))
+        );
+    }
+
+    #[test]
+    fn builds_from_a_block_list() {
+        let mut list = BlockList::new();
+        for height in 0..3 {
+            list.insert_at_tail(block(height));
+        }
+        let store = BlockStore::from(&list);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(1), Some(block(1)));
+    }
+}
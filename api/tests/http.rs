@@ -0,0 +1,238 @@
+use api::http::client::HttpServerApi;
+use api::http::routes::{limited_routes, routes};
+use api::limits::{LimitedServerApi, Limits};
+use api::merkle::{merkle_root, verify_inclusion, ProofError};
+use api::server::*;
+use api::store::BlockStore;
+use futures::{StreamExt, TryStreamExt};
+use std::net::SocketAddr;
+use std::time::Duration;
+use warp::Filter;
+
+fn block(height: u32) -> Block {
+    let transactions: Vec<_> = (0..3u8)
+        .map(|i| Transaction {
+            tx_id: TransactionId([i; 32]),
+            ..Default::default()
+        })
+        .collect();
+    Block {
+        header: BlockHeader {
+            block_height: height,
+            transactions_root: merkle_root(&transactions),
+            ..Default::default()
+        },
+        transactions,
+    }
+}
+
+/// Serve `store` on an ephemeral localhost port.
+fn serve(store: BlockStore) -> SocketAddr {
+    let (addr, server) = warp::serve(routes(store)).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+}
+
+async fn get_json(url: String) -> serde_json::Value {
+    let body = reqwest::get(url).await.unwrap().text().await.unwrap();
+    serde_json::from_str(&body).unwrap()
+}
+
+#[tokio::test]
+async fn fetches_headers_and_transactions() {
+    let store: BlockStore = (0..20).map(block).collect();
+    let client = HttpServerApi::new(format!("http://{}/", serve(store.clone())));
+
+    let headers = client.block_headers(5..15).await.unwrap();
+    assert_eq!(headers, store.block_headers(5..15).await.unwrap());
+
+    let transactions = client.block_transactions(0..20).await.unwrap();
+    assert_eq!(transactions.len(), 20);
+    assert_eq!(transactions[7], block(7).transactions);
+}
+
+#[tokio::test]
+async fn streams_headers_and_transactions() {
+    let store: BlockStore = (0..250).filter(|&h| h != 230).map(block).collect();
+    let client = HttpServerApi::new(format!("http://{}", serve(store.clone())));
+
+    let headers: Vec<_> = client
+        .stream_block_headers(0..230)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(headers, store.block_headers(0..230).await.unwrap());
+    let transactions = client.stream_block_transactions(0..250);
+    let transactions: Vec<_> = transactions.collect().await;
+    assert_eq!(transactions.len(), 201);
+    assert_eq!(transactions[7], Ok(block(7).transactions));
+    assert_eq!(transactions[200], Err(ServerError::MissingHeight(230)));
+
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = 3..1;
+    let mut stream = client.stream_block_headers(reversed);
+    assert_eq!(
+        stream.next().await,
+        Some(Err(ServerError::InvalidRange { start: 3, end: 1 }))
+    );
+    assert_eq!(stream.next().await, None);
+}
+
+#[tokio::test]
+async fn serves_ids_and_hashes_as_hex_strings() {
+    let store: BlockStore = (0..2).map(block).collect();
+    let addr = serve(store);
+
+    let transactions = get_json(format!("http://{}/transactions/1/2", addr)).await;
+    let tx = &transactions[0][1];
+    assert_eq!(tx["tx_id"], format!("0x{}", "01".repeat(32)));
+    assert_eq!(
+        tx["transaction_fields"]["sender"],
+        format!("0x{}", "00".repeat(32))
+    );
+    assert_eq!(tx["signature"], format!("0x{}", "00".repeat(64)));
+
+    let headers = get_json(format!("http://{}/headers/1/2", addr)).await;
+    assert_eq!(
+        headers[0]["transactions_root"],
+        block(1).header.transactions_root.to_string()
+    );
+}
+
+#[tokio::test]
+async fn looks_up_the_tip_blocks_and_transactions() {
+    let store: BlockStore = (0..6).map(block).collect();
+    let client = HttpServerApi::new(format!("http://{}", serve(store)));
+
+    assert_eq!(client.latest_height().await, Ok(5));
+    let hash = block(4).header.hash();
+    assert_eq!(client.header_by_hash(hash).await, Ok(block(4).header));
+    assert_eq!(client.block_by_hash(hash).await, Ok(block(4)));
+    // Every block has the same transaction ids, the last one is kept
+    assert_eq!(
+        client.transaction_by_id(TransactionId([2; 32])).await,
+        Ok(TransactionLocation {
+            block_height: 5,
+            tx_index: 2
+        })
+    );
+
+    let unknown = block(6).header.hash();
+    assert_eq!(
+        client.block_by_hash(unknown).await,
+        Err(ServerError::UnknownBlock(unknown))
+    );
+    assert_eq!(
+        client.transaction_by_id(TransactionId([7; 32])).await,
+        Err(ServerError::UnknownTransaction(TransactionId([7; 32])))
+    );
+    let empty = HttpServerApi::new(format!("http://{}", serve(BlockStore::new())));
+    assert_eq!(
+        empty.latest_height().await,
+        Err(ServerError::MissingHeight(0))
+    );
+}
+
+#[tokio::test]
+async fn fetches_inclusion_proofs() {
+    let store: BlockStore = (0..5).map(block).collect();
+    let client = HttpServerApi::new(format!("http://{}", serve(store)));
+
+    let header = client.block_headers(3..4).await.unwrap()[0];
+    let proof = client.inclusion_proof(3, 1).await.unwrap();
+    assert!(verify_inclusion(&proof, &header));
+
+    assert_eq!(
+        client.inclusion_proof(3, 3).await,
+        Err(ProofError::MissingTransaction {
+            block_height: 3,
+            tx_index: 3
+        })
+    );
+    assert_eq!(
+        client.inclusion_proof(5, 0).await,
+        Err(ProofError::Server(ServerError::MissingHeight(5)))
+    );
+}
+
+#[tokio::test]
+async fn maps_server_errors() {
+    let store: BlockStore = (0..3).map(block).collect();
+    let client = HttpServerApi::new(format!("http://{}", serve(store)));
+
+    assert_eq!(
+        client.block_headers(2..5).await,
+        Err(ServerError::MissingHeight(3))
+    );
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = 3..1;
+    assert_eq!(
+        client.block_transactions(reversed).await,
+        Err(ServerError::InvalidRange { start: 3, end: 1 })
+    );
+}
+
+#[tokio::test]
+async fn maps_status_codes_without_error_body() {
+    let unavailable = warp::any()
+        .map(|| warp::reply::with_status("down", warp::http::StatusCode::SERVICE_UNAVAILABLE));
+    let (addr, server) = warp::serve(unavailable).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let client = HttpServerApi::new(format!("http://{}", addr));
+    assert_eq!(
+        client.block_headers(0..1).await,
+        Err(ServerError::Unavailable)
+    );
+
+    let not_found = warp::any().map(|| warp::http::StatusCode::NOT_FOUND);
+    let (addr, server) = warp::serve(not_found).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let client = HttpServerApi::new(format!("http://{}", addr));
+    assert_eq!(
+        client.block_transactions(4..6).await,
+        Err(ServerError::MissingHeight(4))
+    );
+}
+
+#[tokio::test]
+async fn reports_unreachable_server() {
+    // Bind then drop a listener to get a port nothing listens on.
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let client = HttpServerApi::new(format!("http://{}", addr));
+    assert!(matches!(
+        client.block_headers(0..1).await,
+        Err(ServerError::Transport(_))
+    ));
+}
+
+#[tokio::test]
+async fn maps_limit_errors() {
+    let store: BlockStore = (0..10).map(block).collect();
+    let limited = LimitedServerApi::new(
+        store,
+        Limits {
+            max_range_len: 4,
+            quota: 1,
+            window: Duration::from_secs(60),
+        },
+    );
+    let (addr, server) = warp::serve(limited_routes(limited)).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let client = HttpServerApi::new(format!("http://{}", addr));
+
+    assert_eq!(
+        client.block_headers(0..5).await,
+        Err(ServerError::RangeTooLarge {
+            requested: 5,
+            max: 4
+        })
+    );
+    client.block_headers(0..4).await.unwrap();
+    assert!(matches!(
+        client.block_transactions(0..4).await,
+        Err(ServerError::Throttled { .. })
+    ));
+}
@@ -19,7 +19,7 @@ impl<T> Node<T> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct DoubleLinkedList<T> {
     head: Option<Link<T>>,
     tail: Option<Link<T>>,
@@ -44,6 +44,10 @@ impl<T> DoubleLinkedList<T> {
         self.size
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
     pub fn insert_at_head(&mut self, data: T) {
         let new_node = Arc::new(Mutex::new(Node::new(data)));
         match self.head.take() {
@@ -110,14 +114,38 @@ impl<T> DoubleLinkedList<T> {
         })
     }
 
-    pub fn iter<'a>(&'a self) -> DoubleLinkedListIter<T> {
+    pub fn iter(&self) -> DoubleLinkedListIter<T> {
         DoubleLinkedListIter {
             next: self.head.clone(),
             next_back: self.tail.clone(),
         }
     }
 }
- 
+
+impl<T> Default for DoubleLinkedList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Copies the items into new nodes: a clone sharing the nodes would cut
+/// the links of the original list when it is dropped.
+impl<T: Clone + Default> Clone for DoubleLinkedList<T> {
+    fn clone(&self) -> Self {
+        self.iter().collect()
+    }
+}
+
+impl<T> FromIterator<T> for DoubleLinkedList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = DoubleLinkedList::new();
+        for item in iter {
+            list.insert_at_tail(item);
+        }
+        list
+    }
+}
+
 impl<T> Drop for DoubleLinkedList<T> {
     fn drop(&mut self) {
         while let Some(node) = self.head.take() {
@@ -168,7 +196,6 @@ where
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,5 +218,17 @@ mod tests {
         assert_eq!(iter.next_back(), Some(0));
         assert_eq!(iter.next_back(), None);
     }
-    
+
+    #[test]
+    fn dropping_a_clone_keeps_the_list() {
+        let mut list = DoubleLinkedList::<i32>::new();
+        for i in 0..4 {
+            list.insert_at_tail(i);
+        }
+        let mut clone = list.clone();
+        clone.insert_at_tail(4);
+        drop(clone);
+        assert_eq!(list.len(), 4);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
 }
@@ -1,5 +1,13 @@
-use api::server::*;
-use hex::FromHex;
+use api::disk_store::DiskBlockStore;
+use api::generator::{ChainGenerator, GeneratorConfig};
+use api::limits::{LimitedServerApi, Limits};
+use api::mempool::Mempool;
+use api::producer::{BlockProducer, ProducerConfig};
+use api::store::BlockStore;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use warp::Filter;
 
 use requests::handlers;
 use requests::routes;
@@ -8,103 +16,65 @@ mod requests;
 
 #[tokio::main]
 async fn main() {
-    let mut list_block = BlockList::new();
-    let block_header0 = BlockHeader {
-        block_height: 0,
-        consensus_fields: ConsensusFields {},
-    };
+    // The accounts are the ones of the generator, see `api::generator::signing_key`
+    let mut generator = ChainGenerator::new(GeneratorConfig::default());
+    let genesis_state = generator.genesis_state().clone();
 
-    let transaction0 = Transaction {
-        tx_id: <[u8; 32]>::from_hex(
-            "1e6f77206973207468652074696d6520666f7220616c6c20676f6f64206d656e",
-        )
-        .expect("invalid tx_id"),
-        transaction_fields: TransactionFields {},
-    };
-    let transaction1 = Transaction {
-        tx_id: <[u8; 32]>::from_hex(
-            "2eef77206973207468652074696d6520666f7220616c6c20676f6f64206d656e",
-        )
-        .expect("invalid tx_id"),
-        transaction_fields: TransactionFields {},
-    };
-    let transaction2 = Transaction {
-        tx_id: <[u8; 32]>::from_hex(
-            "3eaf77206973207468652074696d6520666f7220616c6c20676f6f64206d656e",
-        )
-        .expect("invalid tx_id"),
-        transaction_fields: TransactionFields {},
-    };
-    let transaction3 = Transaction {
-        tx_id: <[u8; 32]>::from_hex(
-            "4eff77206973207468652074696d6520666f7220616c6c20676f6f64206d656e",
-        )
-        .expect("invalid tx_id"),
-        transaction_fields: TransactionFields {},
-    };
-    let mut transactions = Vec::new();
-    transactions.push(transaction0);
-    transactions.push(transaction1);
-    transactions.push(transaction2);
-    transactions.push(transaction3);
+    // The chain is kept on disk so it survives restarts, it is only seeded on the first run
+    let store_dir = std::env::var("BLOCK_STORE_DIR").unwrap_or_else(|_| "blocks".to_string());
+    // A store of another format is refused, move it away to start a new chain
+    let store = DiskBlockStore::open(&store_dir)
+        .unwrap_or_else(|e| panic!("block store in {store_dir} cannot be opened: {e}"));
+    if store.is_empty() {
+        for block in generator.by_ref().take(6) {
+            store.append(&block).expect("block is written to the store");
+        }
+    }
+    // Serve the chain as stored, it may have grown in a previous run
+    let chain = BlockStore::new();
+    let mut state = genesis_state.clone();
+    for height in 0..store.len() as u32 {
+        let block = store
+            .get(height)
+            .expect("block is read from the store")
+            .expect("stored heights are contiguous");
+        state
+            .apply_block(&block)
+            .expect("stored blocks execute on the genesis state");
+        chain.insert(block);
+    }
 
-    let block0 = Block {
-        header: block_header0,
-        transactions: transactions.clone(),
-    };
-
-    let block_header1 = BlockHeader {
-        block_height: 1,
-        consensus_fields: ConsensusFields {},
-    };
-    let block1 = Block {
-        header: block_header1,
-        transactions: transactions.clone(),
-    };
-
-    let block_header2 = BlockHeader {
-        block_height: 2,
-        consensus_fields: ConsensusFields {},
-    };
-    let block2 = Block {
-        header: block_header2,
-        transactions: transactions.clone(),
-    };
-
-    let block_header3 = BlockHeader {
-        block_height: 3,
-        consensus_fields: ConsensusFields {},
-    };
-    let block3 = Block {
-        header: block_header3,
-        transactions: transactions.clone(),
-    };
-
-    let block_header4 = BlockHeader {
-        block_height: 4,
-        consensus_fields: ConsensusFields {},
-    };
-    let block4 = Block {
-        header: block_header4,
-        transactions: transactions.clone(),
-    };
-
-    let block_header5 = BlockHeader {
-        block_height: 5,
-        consensus_fields: ConsensusFields {},
-    };
-    let block5 = Block {
-        header: block_header5,
-        transactions: transactions.clone(),
-    };
+    let mempool = Arc::new(Mutex::new(Mempool::new(state, 10_000)));
+    let producer = BlockProducer::new(chain.clone(), mempool.clone(), ProducerConfig::default());
+    let sealed_store = store.clone();
+    // The append syncs the log, keep it off the other tasks of the worker. A
+    // block that cannot be written is kept and written again before the next
+    // ones, so the stored heights stay contiguous.
+    let mut unsaved = VecDeque::new();
+    tokio::spawn(producer.run(move |block| {
+        unsaved.push_back(block.clone());
+        tokio::task::block_in_place(|| {
+            while let Some(block) = unsaved.front() {
+                if let Err(e) = sealed_store.append(block) {
+                    let height = block.header.block_height;
+                    eprintln!("Block {height} is not written to the store yet: {e}");
+                    break;
+                }
+                unsaved.pop_front();
+            }
+        });
+    }));
 
-    list_block.insert_at_head(block5);
-    list_block.insert_at_head(block4);
-    list_block.insert_at_head(block3);
-    list_block.insert_at_head(block2);
-    list_block.insert_at_head(block1);
-    list_block.insert_at_head(block0.clone());
-    let routes = routes::routes(list_block);
+    // Cap the heights per call and the calls per client like a public server would
+    let limits = Limits {
+        max_range_len: 1000,
+        quota: 100,
+        window: Duration::from_secs(1),
+    };
+    let store = LimitedServerApi::new(store, limits);
+    let routes = routes::routes(chain, genesis_state)
+        .or(routes::submit_transaction(mempool))
+        .or(api::http::routes::limited_routes(store));
 
     println!("Server started at http://localhost:8000");
     warp::serve(routes).run(([127, 0, 0, 1], 8000)).await;
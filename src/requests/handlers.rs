@@ -1,33 +1,82 @@
 use api::blocks::*;
+use api::mempool::{Mempool, MempoolError};
 use api::server::*;
-use list::linked_list::*;
-use std::sync::Arc;
+use api::state::WorldState;
+use api::store::BlockStore;
+use std::sync::{Arc, Mutex};
 use tokio::runtime::Handle;
+use warp::http::StatusCode;
+use warp::reply::Reply;
 
+/// Reply with the built blocks, or with the builder error and the matching status code.
+fn blocks_reply(result: Result<Vec<Block>, BuildError>) -> warp::reply::Response {
+    match result {
+        Ok(blocks) => warp::reply::json(&blocks).into_response(),
+        Err(e) => {
+            let status = match e {
+                BuildError::Server(_) => StatusCode::BAD_GATEWAY,
+                BuildError::StateTransition(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                BuildError::Storage(_) | BuildError::Task(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            warp::reply::with_status(warp::reply::json(&e), status).into_response()
+        }
+    }
+}
+
+/// Build the blocks fetching ahead in parallel and execute them in order on `genesis`
+///
+/// Each block spends from the state left by its parent, so the transfers
+/// without funds or out of nonce order are reported.
 pub async fn get_blocks_in_parallel(
-    list_blocks: DoubleLinkedList<Block>,
+    list_blocks: BlockStore,
+    mut genesis: WorldState,
     end_range: u32,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let arclist = Arc::new(list_blocks);
     let handle = Handle::current();
     // let blocks_parallel = arclist.build_blocks_parallel(0..end_range).await;
     let handle = std::thread::spawn(move || {
-     return handle.block_on(arclist.build_blocks_parallel(0..end_range)).unwrap();
+        let built =
+            arclist.build_blocks_pipelined_on(&mut genesis, 0..end_range, BuildConfig::default());
+        handle.block_on(built)
     });
     let result = handle.join().unwrap();
-    Ok(warp::reply::json(&result))
+    Ok(blocks_reply(result))
 }
 
 pub async fn get_blocks_in_backward(
-    list_blocks: DoubleLinkedList<Block>,
+    list_blocks: BlockStore,
+    mut genesis: WorldState,
     end_range: u32,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let blocks: Vec<Block> = vec![];
-    let arclist = Arc::new(list_blocks);
-    let blocks_parallel = arclist
-        .build_blocks_backward(blocks.clone(), 0..end_range)
+    let blocks_backward = list_blocks
+        .build_blocks_sequential_on(&mut genesis, 0..end_range, BuildConfig::default())
         .await;
-    Ok(warp::reply::json(&blocks_parallel))
+    Ok(blocks_reply(blocks_backward))
+}
+
+/// Add the transaction to the mempool, it is sealed in one of the next blocks.
+pub async fn submit_transaction(
+    mempool: Arc<Mutex<Mempool>>,
+    transaction: Transaction,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = match mempool.lock().unwrap().submit(transaction) {
+        Ok(()) => {
+            warp::reply::with_status(warp::reply::json(&transaction.tx_id), StatusCode::ACCEPTED)
+                .into_response()
+        }
+        Err(e) => {
+            let status = match e {
+                MempoolError::AlreadyKnown(_) | MempoolError::Underpriced { .. } => {
+                    StatusCode::CONFLICT
+                }
+                MempoolError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                MempoolError::Full => StatusCode::SERVICE_UNAVAILABLE,
+            };
+            warp::reply::with_status(warp::reply::json(&e), status).into_response()
+        }
+    };
+    Ok(response)
 }
 
 //    pub async fn get_blocks_in_forward(list_blocks: DoubleLinkedList<Block>, end_range:u32) -> Result<impl warp::Reply, warp::Rejection> {
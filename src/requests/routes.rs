@@ -1,43 +1,87 @@
 use crate::handlers;
+use api::mempool::Mempool;
 use api::server::*;
-use list::linked_list::*;
+use api::state::WorldState;
+use api::store::BlockStore;
+use std::sync::{Arc, Mutex};
 use warp::Filter;
 
+/// The blocks are executed on `genesis`, the world state before the first block
 pub fn routes(
-    list_blocks: DoubleLinkedList<Block>,
+    blocks: BlockStore,
+    genesis: WorldState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    get_blocks_in_parallel(list_blocks.clone()).or(get_blocks_in_backward(list_blocks.clone()))
+    get_blocks_in_parallel(blocks.clone(), genesis.clone())
+        .or(get_blocks_in_backward(blocks.clone(), genesis))
     // get_blocks_in_forward();
 }
 
 pub fn get_blocks_in_parallel(
-    list_blocks: DoubleLinkedList<Block>,
+    blocks: BlockStore,
+    genesis: WorldState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("blocks" / u32)
         .and(warp::get())
         .and_then(move |end: u32| {
-            let blocks = list_blocks.clone();
-            return async move {
-                handlers::get_blocks_in_parallel(blocks.clone(), end)
-                    .await
-                    .map_err(|e| e)
-            };
+            // Clones share the indexed chain, the producer keeps extending it
+            handlers::get_blocks_in_parallel(blocks.clone(), genesis.clone(), end)
         })
 }
 
 pub fn get_blocks_in_backward(
-    list_blocks: DoubleLinkedList<Block>,
+    blocks: BlockStore,
+    genesis: WorldState,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("blocks" / u32)
         .and(warp::get())
         .and_then(move |end: u32| {
-            let blocks = list_blocks.clone();
-            return async move {
-                handlers::get_blocks_in_backward(blocks.clone(), end)
-                    .await
-                    // .map(|result| warp::reply::json(&result))
-                    .map_err(|e| e)
-                // .map_err(|e| warp::reject::custom(e))
-            };
+            handlers::get_blocks_in_backward(blocks.clone(), genesis.clone(), end)
+        })
+}
+
+pub fn submit_transaction(
+    mempool: Arc<Mutex<Mempool>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("transactions")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(move |transaction: Transaction| {
+            handlers::submit_transaction(mempool.clone(), transaction)
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::generator::{ChainGenerator, GeneratorConfig};
+    use warp::http::StatusCode;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reports_the_transfers_failing_on_the_world_state() {
+        for (height, fault) in [
+            (3, StateTransitionReason::InsufficientBalance),
+            (4, StateTransitionReason::BadNonce),
+        ] {
+            let config = GeneratorConfig {
+                faults: [(height, fault)].into_iter().collect(),
+                ..Default::default()
+            };
+            let generator = ChainGenerator::new(config);
+            let genesis = generator.genesis_state().clone();
+            let blocks: BlockStore = generator.take(6).collect();
+
+            let parallel = get_blocks_in_parallel(blocks.clone(), genesis.clone());
+            let backward = get_blocks_in_backward(blocks, genesis);
+            let request = || warp::test::request().path("/blocks/6");
+            for response in [
+                request().reply(&parallel).await,
+                request().reply(&backward).await,
+            ] {
+                assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+                let body = String::from_utf8(response.body().to_vec()).unwrap();
+                assert!(body.contains(&format!("\"block_height\":{height}")));
+                assert!(body.contains(&format!("\"reason\":\"{fault:?}\"")));
+            }
+        }
+    }
+}